@@ -0,0 +1,359 @@
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::CacheConfig;
+use crate::fs_tools;
+use crate::git::{CloneOptions, GitBackend, LogOptions};
+use crate::remote::RemoteSpec;
+
+/// File in each entry recording what it holds and when it was last used.
+const ENTRY_INFO: &str = "entry.json";
+
+/// Clones of `--remote` repositories kept between runs, one entry per URL, revision and
+/// directory. Entries are updated in place on later runs and pruned, least recently used
+/// first, when the cache grows beyond its size limit.
+pub struct RemoteCache {
+    root: PathBuf,
+    max_size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EntryInfo {
+    url: String,
+    rev: Option<String>,
+    subdir: Option<String>,
+    /// Seconds since the epoch.
+    last_used: u64,
+}
+
+/// A checked out cache entry. The entry stays locked against pruning and updates by
+/// other runs until this is dropped.
+pub struct Checkout {
+    pub repo: PathBuf,
+    _lock: File,
+}
+
+/// What `RemoteCache::prune` removed.
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub removed: usize,
+    pub freed: u64,
+    pub remaining: u64,
+}
+
+impl RemoteCache {
+    /// The cache in `dir` (`--cache-dir`), else the configured or platform cache directory.
+    pub fn new(config: &CacheConfig, dir: Option<&str>) -> Result<Self> {
+        let root = match dir.or(config.dir.as_deref()) {
+            Some(dir) => PathBuf::from(dir),
+            None => default_dir()
+                .context("No cache directory found; set one with --cache-dir")?
+                .join("rustymix"),
+        };
+        Ok(Self {
+            root: root.join("remotes"),
+            max_size: parse_size(&config.max_size)
+                .with_context(|| format!("Invalid cache.maxSize {:?}", config.max_size))?,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.root
+    }
+
+    /// The configured size limit in bytes.
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// The checkout of `spec`: cloned on first use, updated on later ones. A full commit
+    /// hash cannot move, so its checkout is used as it is; so is a checkout that fails to
+    /// update. Reports progress through `status`, and prunes other entries beyond the size
+    /// limit afterwards.
+    pub fn checkout(
+        &self,
+        git: &dyn GitBackend,
+        spec: &RemoteSpec,
+        status: impl Fn(&str),
+    ) -> Result<Checkout> {
+        let key = fs_tools::content_hash(&format!(
+            "{}\n{}\n{}",
+            spec.url,
+            spec.rev.as_deref().unwrap_or_default(),
+            spec.subdir.as_deref().unwrap_or_default()
+        ));
+        let entry = self.root.join(&key[..16]);
+        let repo = entry.join("repo");
+        let options = CloneOptions {
+            rev: spec.rev.clone(),
+            sparse: spec.subdir.clone(),
+        };
+        // Other runs neither update nor prune the entry while this one holds it
+        fs::create_dir_all(&self.root)?;
+        let lock = lock_entry(&entry)?;
+
+        let usable = if !is_usable(git, &repo, spec) {
+            false
+        } else if spec.rev.as_deref().is_some_and(is_full_hash) {
+            status("Using cached clone of remote repository...");
+            true
+        } else {
+            status("Updating cached clone of remote repository...");
+            if let Err(e) = git.update_clone(&spec.url, &repo, &options) {
+                eprintln!(
+                    "Warning: cannot update the cached clone of {}, using it as it is: {:#}",
+                    spec.url, e
+                );
+            }
+            is_usable(git, &repo, spec)
+        };
+        if !usable {
+            status("Cloning remote repository...");
+            fs::create_dir_all(&entry)?;
+            // Clone beside the old checkout and replace it only once the clone is complete
+            let partial = entry.join("repo.partial");
+            if partial.exists() {
+                fs::remove_dir_all(&partial)?;
+            }
+            git.clone_repo(&spec.url, &partial, &options)?;
+            if repo.exists() {
+                fs::remove_dir_all(&repo)?;
+            }
+            fs::rename(&partial, &repo)?;
+        }
+
+        let info = EntryInfo {
+            url: spec.url.clone(),
+            rev: spec.rev.clone(),
+            subdir: spec.subdir.clone(),
+            last_used: now(),
+        };
+        fs::write(entry.join(ENTRY_INFO), serde_json::to_string_pretty(&info)?)?;
+
+        if let Err(e) = self.prune(self.max_size, Some(&entry)) {
+            eprintln!("Failed to prune the remote cache: {:#}", e);
+        }
+        // Readers may share the entry; only updates and pruning need it alone
+        FileExt::lock_shared(&lock)
+            .with_context(|| format!("Failed to lock {}", entry.display()))?;
+        Ok(Checkout { repo, _lock: lock })
+    }
+
+    /// Removes entries, least recently used first, until the cache takes at most
+    /// `max_size` bytes. `keep` is never removed.
+    pub fn prune(&self, max_size: u64, keep: Option<&Path>) -> Result<PruneReport> {
+        let mut report = PruneReport::default();
+        if !self.root.is_dir() {
+            return Ok(report);
+        }
+        let mut entries = Vec::new();
+        for dir in fs::read_dir(&self.root)? {
+            let path = dir?.path();
+            if !path.is_dir() {
+                // Lock files whose entry is gone, unless a run is about to create it
+                let entry = path.with_extension("");
+                if path.extension().is_some_and(|e| e == "lock") && !entry.exists() {
+                    if let Some(lock) = try_lock_entry(&entry)? {
+                        if !entry.exists() {
+                            remove_lock(&entry, lock)?;
+                        }
+                    }
+                }
+                continue;
+            }
+            // Entries without readable info (e.g. interrupted clones) go first
+            let last_used = fs::read_to_string(path.join(ENTRY_INFO))
+                .ok()
+                .and_then(|info| serde_json::from_str::<EntryInfo>(&info).ok())
+                .map_or(0, |info| info.last_used);
+            let size = dir_size(&path)?;
+            report.remaining += size;
+            entries.push((last_used, path, size));
+        }
+        entries.sort();
+
+        for (_, path, size) in entries {
+            if report.remaining <= max_size {
+                break;
+            }
+            if keep == Some(path.as_path()) {
+                continue;
+            }
+            // Entries in use by another run stay
+            let Some(lock) = try_lock_entry(&path)? else {
+                continue;
+            };
+            fs::remove_dir_all(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            remove_lock(&path, lock)?;
+            report.removed += 1;
+            report.freed += size;
+            report.remaining -= size;
+        }
+        Ok(report)
+    }
+}
+
+/// Parses a size such as `2G`, `500M`, `64K` or a plain number of bytes.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid size {:?}", size))?;
+    let unit = match unit
+        .trim()
+        .to_ascii_uppercase()
+        .trim_end_matches(['B', 'I'])
+    {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => anyhow::bail!("Invalid size unit in {:?}; use K, M, G or T", size),
+    };
+    Ok(number.saturating_mul(unit))
+}
+
+/// Human readable form of a byte count, e.g. `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+/// The lock file of a cache entry. It lives beside the entry and is removed with it, by
+/// the run holding the lock.
+fn lock_path(entry: &Path) -> PathBuf {
+    entry.with_extension("lock")
+}
+
+fn open_lock(entry: &Path) -> Result<File> {
+    let path = lock_path(entry);
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// Locks `entry` for this run alone, waiting for other runs to let go of it.
+fn lock_entry(entry: &Path) -> Result<File> {
+    loop {
+        let lock = open_lock(entry)?;
+        lock.lock_exclusive()
+            .with_context(|| format!("Failed to lock {}", entry.display()))?;
+        // The run that held the lock may have pruned the entry and its lock file
+        if is_current(&lock, entry) {
+            return Ok(lock);
+        }
+    }
+}
+
+/// Locks `entry` for this run alone, unless another run holds it.
+fn try_lock_entry(entry: &Path) -> Result<Option<File>> {
+    let lock = open_lock(entry)?;
+    if lock.try_lock_exclusive().is_err() || !is_current(&lock, entry) {
+        return Ok(None);
+    }
+    Ok(Some(lock))
+}
+
+/// Removes the lock file of an entry that was just removed, while still holding it.
+fn remove_lock(entry: &Path, lock: File) -> Result<()> {
+    let path = lock_path(entry);
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    drop(lock);
+    Ok(())
+}
+
+/// Whether `lock` is still the lock file of `entry`, not one since removed.
+fn is_current(lock: &File, entry: &Path) -> bool {
+    let Ok(current) = fs::metadata(lock_path(entry)) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        lock.metadata()
+            .is_ok_and(|m| (m.dev(), m.ino()) == (current.dev(), current.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (lock, current);
+        true
+    }
+}
+
+/// Whether `repo` is a checkout whose commit can still be read. Clones of local
+/// repositories may borrow objects from their source, which can since have been removed
+/// or rewritten.
+fn is_usable(git: &dyn GitBackend, repo: &Path, spec: &RemoteSpec) -> bool {
+    let options = LogOptions {
+        rev: spec.rev.clone().filter(|rev| is_full_hash(rev)),
+        max_count: Some(1),
+        ..Default::default()
+    };
+    repo.is_dir() && git.is_repo(repo) && git.log(repo, &options).is_ok()
+}
+
+fn is_full_hash(rev: &str) -> bool {
+    matches!(rev.len(), 40 | 64) && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Total size of the files below `path`; symlinks are not followed.
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if kind.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// The platform's per-user cache directory.
+fn default_dir() -> Option<PathBuf> {
+    let var = |name: &str| {
+        std::env::var_os(name)
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+    };
+    if cfg!(windows) {
+        return var("LOCALAPPDATA");
+    }
+    if cfg!(target_os = "macos") {
+        return var("HOME").map(|home| home.join("Library/Caches"));
+    }
+    var("XDG_CACHE_HOME").or_else(|| var("HOME").map(|home| home.join(".cache")))
+}
@@ -1,116 +1,348 @@
-use clap::{Parser, ValueEnum};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
 
 #[derive(Parser, Debug, Clone)]
 #[command(name = "rustymix", version, about = "Pack your repository into a single AI-friendly file")]
 pub struct Cli {
-    /// List of directories to process. Defaults to the current directory (".").
+    /// List of directories to process. Defaults to the current directory ("."). A directory
+    /// named like a subcommand needs `rustymix pack <DIR>` or `rustymix -- <DIR>`.
     #[arg(default_value = ".")]
     pub directories: Vec<String>,
 
     /// The output file path. If not specified, output may go to stdout or be generated based on intent.
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub output: Option<String>,
 
-    /// The output format style.
-    #[arg(long, value_enum, default_value_t = OutputStyle::Xml)]
-    pub style: OutputStyle,
+    /// The output format style (default: xml). Several comma-separated styles write one
+    /// file each from a single run, e.g. `--style xml,markdown`.
+    #[arg(long, global = true, value_enum, value_delimiter = ',')]
+    pub style: Option<Vec<OutputStyle>>,
+
+    /// A template file (Jinja syntax) used to render the output. Implies `--style template`.
+    #[arg(long, global = true)]
+    pub template: Option<String>,
+
+    /// Split the output into numbered, self-contained parts of at most this many tokens
+    /// (e.g. rustymix-output.1.xml, rustymix-output.2.xml, ...).
+    #[arg(long, global = true)]
+    pub split_output: Option<usize>,
+
+    /// Maximum tokens per record for the `jsonl` chunk style.
+    #[arg(long, global = true)]
+    pub chunk_max_tokens: Option<usize>,
+
+    /// Wrap file contents, header, instructions and git output in CDATA sections
+    /// (`xml` style) instead of entity-escaping them, keeping code readable.
+    #[arg(long, global = true)]
+    pub xml_cdata: bool,
+
+    /// Comma-separated metadata shown in each file header:
+    /// language, lines, tokens, bytes, commit, mode, truncated.
+    #[arg(long, global = true, value_enum, value_delimiter = ',')]
+    pub file_metadata: Option<Vec<FileMetadata>>,
 
     /// [Deprecated] Enable a machine-parsable style if applicable.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub parsable_style: bool,
 
     /// Path to a specific configuration file (e.g., rustymix.config.json).
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub config: Option<String>,
 
     /// Copy the generated output to the system clipboard.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub copy: bool,
 
     /// Enable verbose logging for debugging purposes.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub verbose: bool,
 
-    /// The number of "top files" to display in the summary (based on some metric like modification count).
-    #[arg(long)]
+    /// The number of "top files" (by token and character count) to list in the summary and console report.
+    #[arg(long, global = true)]
     pub top_files_len: Option<usize>,
 
     /// Add line numbers to the source code in the output.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub output_show_line_numbers: bool,
 
     /// Remove comments from the source code (supported languages only).
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub remove_comments: bool,
 
     /// Remove empty lines to compact the code.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub remove_empty_lines: bool,
 
     /// aggressively compress the code (remove extra whitespace, newlines, etc.).
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub compress: bool,
 
     /// Include empty directories in the file listing.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub include_empty_directories: bool,
 
-    /// A remote repository URL to clone and process.
-    #[arg(long)]
+    /// Annotate each directory in the tree with its file count and token total.
+    #[arg(long, global = true)]
+    pub tree_metrics: bool,
+
+    /// A remote repository to clone and process: a clone URL, `owner/repo` (GitHub), or a
+    /// URL into a directory, `<url>/tree/<rev>/<dir>`, to check out only that directory.
+    #[arg(long, global = true)]
     pub remote: Option<String>,
 
-    /// The branch to check out for the remote repository.
-    #[arg(long)]
+    /// The branch, tag or commit to check out for the remote repository.
+    #[arg(long, global = true)]
     pub remote_branch: Option<String>,
 
+    /// Clone the remote repository afresh into a temporary directory instead of using
+    /// and updating the cached clone.
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    /// Where cloned remote repositories are kept (default: the platform cache directory,
+    /// e.g. `~/.cache/rustymix`).
+    #[arg(long, value_name = "DIR", env = "RUSTYMIX_CACHE_DIR", global = true)]
+    pub cache_dir: Option<String>,
+
+    /// Pack the directory as of this git revision (branch, tag or commit), reading files
+    /// from the object database without touching the working tree.
+    #[arg(long = "ref", value_name = "REV", global = true)]
+    pub git_ref: Option<String>,
+
+    /// How to read git repositories: built in, through the `git` executable, or built in
+    /// with the executable as a fallback.
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        env = "RUSTYMIX_GIT_BACKEND",
+        global = true
+    )]
+    pub git_backend: GitBackendKind,
+
+    /// How to order files in the pack; every order puts the files that matter most last,
+    /// closest to the question (default: `churn` inside a git repository, else `path`).
+    #[arg(long, value_enum, global = true)]
+    pub sort: Option<SortOrder>,
+
+    /// Number of recent commits whose changes `--sort churn` counts (default: 100).
+    #[arg(long, value_name = "N", global = true)]
+    pub churn_window: Option<usize>,
+
     /// Enable or disable the security check for suspicious content (e.g. secrets).
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub security_check: Option<bool>,
 
     /// Additional glob patterns to include (overriding ignores).
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub include: Option<String>,
 
     /// Additional glob patterns to ignore.
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub ignore: Option<String>,
 
     /// Disable the use of .gitignore files.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub no_gitignore: bool,
 
     /// Disable default ignore patterns entry (e.g. .git, node_modules).
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub no_default_patterns: bool,
 
     /// Custom text to include in the header of the output.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub header_text: Option<String>,
 
     /// Path to a file containing instructions/text to include in the header.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub instruction_file_path: Option<String>,
 
     /// Include git diffs in the output (if in a git repository).
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub include_diffs: bool,
 
+    /// Diff against the merge base of this ref and HEAD (e.g. `main`). Implies `--include-diffs`.
+    #[arg(long, global = true, conflicts_with = "diff_range")]
+    pub diff_base: Option<String>,
+
+    /// Diff an explicit commit range such as `v1.0..HEAD`. Implies `--include-diffs`.
+    #[arg(long, global = true, conflicts_with_all = ["diff_staged", "diff_unstaged"])]
+    pub diff_range: Option<String>,
+
+    /// Only include staged changes in the diff. Implies `--include-diffs`.
+    #[arg(long, global = true, conflicts_with = "diff_unstaged")]
+    pub diff_staged: bool,
+
+    /// Only include unstaged working-tree changes (and untracked files) in the diff.
+    /// Implies `--include-diffs`.
+    #[arg(long, global = true, conflicts_with = "diff_base")]
+    pub diff_unstaged: bool,
+
     /// Include git log history in the output (if in a git repository).
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub include_logs: bool,
 
+    /// Attach to each focused file its last N commits and the line ranges those commits
+    /// changed (from `git blame`, numbered as in the original file, not the packed one).
+    #[arg(long, value_name = "N", global = true)]
+    pub file_history: Option<usize>,
+
+    /// Number of commits in the git log (default: 50). Implies `--include-logs`.
+    #[arg(long, value_name = "N", global = true)]
+    pub log_count: Option<usize>,
+
+    /// Only log commits after this date, e.g. `"2 weeks ago"` or `2024-01-31`. Implies
+    /// `--include-logs`.
+    #[arg(long, value_name = "DATE", global = true)]
+    pub log_since: Option<String>,
+
+    /// Attach each logged commit's diff, limited to the packed files. Implies
+    /// `--include-logs`.
+    #[arg(long, global = true)]
+    pub log_diffs: bool,
+
+    /// Only log commits touching files packed in full (the focused files). Implies
+    /// `--include-logs`.
+    #[arg(long, global = true)]
+    pub log_focused: bool,
+
+    /// Only emit files added or changed since the previous pack of the same intent,
+    /// plus a summary of what was added, changed and removed.
+    #[arg(long, global = true)]
+    pub since_last: bool,
+
     // --- NEW ARGUMENTS ---
 
     /// The specific task you want the LLM to perform.
     /// If provided, this generates a custom prompt at the top of the file.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub intent: Option<String>,
 
     /// A comma-separated list of files to include in FULL TEXT, overriding compression.
     /// Example: --focus "src/main.rs,src/utils.rs"
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub focus: Option<String>,
+
+    /// Focus on every file changed since the merge base with this ref (default: the
+    /// repository's default branch), plus staged, unstaged and untracked files.
+    /// Combines with `--focus`. The ref must be attached, `--focus-changed=<BASE_REF>`, so a
+    /// following directory is not taken for it.
+    #[arg(
+        long,
+        global = true,
+        value_name = "BASE_REF",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    pub focus_changed: Option<String>,
+
+    /// How the survey prompt asks the model to reply: a `cli` argument line, or a `json`
+    /// object following the reply schema embedded in the prompt.
+    #[arg(long, global = true, value_enum)]
+    pub survey_format: Option<SurveyFormat>,
+
+    /// A JSON survey reply (file path, or `-` for stdin). It is validated against the
+    /// reply schema and the repository's files, then its focus and ignore patterns are
+    /// merged into `--focus` and `--ignore`.
+    #[arg(long, global = true)]
+    pub survey_reply: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// The global flags `cache` takes; every other global flag is a pack flag.
+const CACHE_FLAGS: &[&str] = &["config", "verbose", "cache_dir"];
+
+impl Cli {
+    /// Parses the command line like [`Parser::parse`], keeping pack flags off `cache`.
+    pub fn parse_args() -> Self {
+        let mut cmd = Self::command();
+        let pack_flags: Vec<Arg> = cmd
+            .get_arguments()
+            .filter(|a| a.is_global_set() && !CACHE_FLAGS.contains(&a.get_id().as_str()))
+            .cloned()
+            .collect();
+        // clap hands global flags to every subcommand unless it has its own, so `cache`
+        // gets hidden local copies that stop them before its actions
+        cmd = cmd.mut_subcommand("cache", |cache| {
+            cache.args(
+                pack_flags
+                    .iter()
+                    .map(|a| a.clone().global(false).hide(true)),
+            )
+        });
+
+        let matches = cmd.get_matches_mut();
+        if let Some(("cache", cache)) = matches.subcommand() {
+            // Flags before the action reach the top level or `cache` itself
+            let given = pack_flags.iter().find(|a| {
+                [&matches, cache]
+                    .iter()
+                    .any(|m| m.value_source(a.get_id().as_str()) == Some(ValueSource::CommandLine))
+            });
+            if let Some(flag) = given {
+                let action = cache.subcommand_name().expect("cache requires an action");
+                cmd.find_subcommand_mut("cache")
+                    .and_then(|c| c.find_subcommand_mut(action))
+                    .expect("parsed cache action")
+                    .error(
+                        ErrorKind::UnknownArgument,
+                        format!(
+                            "unexpected argument '--{}' found",
+                            flag.get_long().unwrap_or(flag.get_id().as_str())
+                        ),
+                    )
+                    .exit();
+            }
+        }
+        Self::from_arg_matches(&matches).unwrap_or_else(|e| e.format(&mut cmd).exit())
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Pack directories, the same as running without a subcommand. Useful when a
+    /// directory is named like a subcommand, e.g. `rustymix pack review`.
+    Pack {
+        /// List of directories to process.
+        #[arg(default_value = ".")]
+        directories: Vec<String>,
+    },
+    /// Pack a change set for review: files changed in `<base>..<head>` in full at the head
+    /// revision with their diffs, the commit messages of the range, and everything else as
+    /// skeleton. Pack options (`-o`, `--style`, `--intent`, ...) apply as usual.
+    Review {
+        /// The range to review, e.g. `main..feature` or `v1.0..` (head defaults to HEAD).
+        range: String,
+
+        /// The repository (or a directory inside it) to review.
+        #[arg(default_value = ".")]
+        directory: String,
+    },
+    /// Manage the cache of cloned remote repositories.
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum CacheCommand {
+    /// Remove cached clones, least recently used first, until the cache fits its size
+    /// limit (`cache.maxSize`, default 2G).
+    Prune {
+        /// Size to prune down to instead of the configured limit, e.g. `500M`.
+        #[arg(long, value_name = "SIZE", conflicts_with = "all")]
+        max_size: Option<String>,
+
+        /// Remove every cached clone.
+        #[arg(long)]
+        all: bool,
+    },
 }
 
 #[derive(ValueEnum, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -120,4 +352,73 @@ pub enum OutputStyle {
     Markdown,
     Json,
     Plain,
+    Yaml,
+    /// A self-contained HTML report for reviewing a pack before sending it.
+    Html,
+    /// One JSON record per semantic code chunk, for retrieval/embedding pipelines.
+    Jsonl,
+    /// A user supplied template file (see `--template`).
+    Template,
+}
+
+/// Per-file facts that can be shown in file headers (`--file-metadata`).
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileMetadata {
+    /// Language detected from the file name.
+    Language,
+    /// Line count on disk, before compression.
+    Lines,
+    /// Token count of the packed content.
+    Tokens,
+    /// Size on disk.
+    Bytes,
+    /// Hash, date and author of the last commit touching the file.
+    Commit,
+    /// `full` or `skeleton`.
+    Mode,
+    /// Whether only part of the file is in this output (split packs).
+    Truncated,
+}
+
+/// File ordering strategies (`--sort`). Ties are broken by path.
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Alphabetically by path.
+    Path,
+    /// Least changed first, by commits in the churn window touching the file.
+    Churn,
+    /// Least recently committed first; uncommitted files last.
+    Recency,
+    /// Smallest token count first.
+    Tokens,
+    /// Files packed in full last, then by how many words of the intent the path and
+    /// content mention.
+    Relevance,
+    /// Imported files before the files importing them (Rust, Python, JavaScript,
+    /// TypeScript and Go).
+    Dependency,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SurveyFormat {
+    /// A single line of `--focus`/`--ignore` arguments.
+    #[default]
+    Cli,
+    /// A JSON object matching the survey reply schema.
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GitBackendKind {
+    /// Built in, falling back to `git` for what it cannot do (e.g. cloning over the
+    /// network).
+    #[default]
+    Auto,
+    /// Built in only; no `git` executable needed.
+    Native,
+    /// The `git` executable only.
+    Cli,
 }
@@ -1,4 +1,4 @@
-use crate::cli::OutputStyle;
+use crate::cli::{FileMetadata, OutputStyle, SortOrder, SurveyFormat};
 use serde::{Deserialize, Serialize};
 
 pub const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
@@ -17,6 +17,8 @@ pub struct RustymixConfig {
     pub output: OutputConfig,
     pub ignore: IgnoreConfig,
     pub security: SecurityConfig,
+    pub prompts: PromptConfig,
+    pub cache: CacheConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -24,6 +26,12 @@ pub struct RustymixConfig {
 pub struct OutputConfig {
     pub file_path: String,
     pub style: OutputStyle,
+    /// Several styles rendered from one run; takes precedence over `style` when set.
+    pub styles: Vec<OutputStyle>,
+    pub template_path: Option<String>,
+    pub chunk_max_tokens: usize,
+    pub xml_cdata: bool,
+    pub file_metadata: Vec<FileMetadata>,
     pub top_files_length: usize,
     pub show_line_numbers: bool,
     pub remove_comments: bool,
@@ -33,8 +41,23 @@ pub struct OutputConfig {
     pub header_text: Option<String>,
     pub instruction_file_path: Option<String>,
     pub include_empty_directories: bool,
+    pub directory_tree_metrics: bool,
+    /// File order; unset picks `churn` inside a git repository, else `path`.
+    pub sort: Option<SortOrder>,
+    /// Recent commits counted by the `churn` order.
+    pub churn_window: usize,
     pub include_diffs: bool,
     pub include_logs: bool,
+    /// Commits in the log section.
+    pub log_count: usize,
+    /// Only commits after this date, e.g. `2 weeks ago` or `2024-01-31`.
+    pub log_since: Option<String>,
+    /// Attach each commit's diff, limited to packed files.
+    pub log_diffs: bool,
+    /// Only commits touching files packed in full.
+    pub log_focused: bool,
+    /// Recent commits and blamed line ranges per file packed in full; 0 turns it off.
+    pub file_history: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -51,11 +74,40 @@ pub struct SecurityConfig {
     pub enable_security_check: bool,
 }
 
+/// Files replacing the built-in survey/build/review instructions injected with `--intent`.
+/// All are templates with `intent`, `focus`, `totalFiles`, `totalTokens`,
+/// `fullTokens` and `skeletonTokens` placeholders; JSON survey prompts also get
+/// `replySchema`, and review prompts `review` (`range`, `head`, `commits`, `files`).
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PromptConfig {
+    pub survey_path: Option<String>,
+    pub build_path: Option<String>,
+    pub review_path: Option<String>,
+    pub survey_format: SurveyFormat,
+}
+
+/// Clones of `--remote` repositories kept between runs.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Defaults to the platform cache directory, e.g. `~/.cache/rustymix`.
+    pub dir: Option<String>,
+    /// Least recently used entries are pruned beyond this size, e.g. `500M` or `2G`.
+    pub max_size: String,
+}
+
 impl Default for OutputConfig {
     fn default() -> Self {
         Self {
             file_path: "rustymix-output.xml".to_string(),
             style: OutputStyle::Xml,
+            styles: Vec::new(),
+            template_path: None,
+            chunk_max_tokens: 512,
+            xml_cdata: false,
+            file_metadata: vec![FileMetadata::Mode],
             top_files_length: 5,
             show_line_numbers: false,
             remove_comments: false,
@@ -65,8 +117,16 @@ impl Default for OutputConfig {
             header_text: None,
             instruction_file_path: None,
             include_empty_directories: false,
+            directory_tree_metrics: false,
+            sort: None,
+            churn_window: 100,
             include_diffs: false,
             include_logs: false,
+            log_count: 50,
+            log_since: None,
+            log_diffs: false,
+            log_focused: false,
+            file_history: 0,
         }
     }
 }
@@ -81,6 +141,16 @@ impl Default for IgnoreConfig {
     }
 }
 
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: None,
+            max_size: "2G".to_string(),
+        }
+    }
+}
+
 impl Default for SecurityConfig {
     fn default() -> Self {
         Self {
@@ -1,11 +1,63 @@
-use tiktoken_rs::cl100k_base;
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use tiktoken_rs::{cl100k_base, CoreBPE};
+
+fn bpe() -> &'static CoreBPE {
+    // Building the BPE tables is expensive, so share one instance across all calls
+    static BPE: OnceLock<CoreBPE> = OnceLock::new();
+    BPE.get_or_init(|| cl100k_base().unwrap())
+}
 
 pub fn count_tokens(content: &str) -> usize {
-    let bpe = cl100k_base().unwrap();
-    bpe.encode_with_special_tokens(content).len()
+    bpe().encode_with_special_tokens(content).len()
+}
+
+/// Byte offsets cutting `content` into consecutive pieces of at most `max_tokens` tokens
+/// each, at character boundaries; the last offset is `content.len()`.
+pub fn token_cuts(content: &str, max_tokens: usize) -> Vec<usize> {
+    let bpe = bpe();
+    // Where each token ends in `content`
+    let mut ends = Vec::new();
+    let mut offset = 0;
+    for token in bpe.encode_with_special_tokens(content) {
+        offset += bpe._decode_native(&[token]).len();
+        ends.push(offset.min(content.len()));
+    }
+
+    let mut cuts = Vec::new();
+    let (mut start, mut first_token) = (0, 0);
+    while start < content.len() {
+        let mut taken = max_tokens.max(1).min(ends.len() - first_token);
+        let cut = loop {
+            let mut cut = ends[first_token + taken - 1];
+            while !content.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            // Re-encoding a piece can merge differently at its edges; take fewer if so
+            if cut > start && (taken == 1 || count_tokens(&content[start..cut]) <= max_tokens) {
+                break cut;
+            }
+            if taken == 1 {
+                // A single character spread over several tokens
+                let next = content[start..].chars().next().map_or(0, char::len_utf8);
+                break start + next;
+            }
+            taken -= 1;
+        };
+        cuts.push(cut);
+        start = cut;
+        while first_token < ends.len() && ends[first_token] <= start {
+            first_token += 1;
+        }
+    }
+    cuts
 }
 
 pub fn is_binary(content: &[u8]) -> bool {
     let len = std::cmp::min(content.len(), 8192);
     content[0..len].contains(&0)
 }
+
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
@@ -1,65 +1,529 @@
-use anyhow::{Context, Result};
-use std::collections::HashMap;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
-use std::process::Command;
 
-pub fn is_git_repo(path: &Path) -> bool {
-    Command::new("git")
-        .arg("rev-parse")
-        .arg("--is-inside-work-tree")
-        .current_dir(path)
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+use crate::cli::GitBackendKind;
+
+mod cli;
+mod native;
+
+use cli::CliBackend;
+use native::NativeBackend;
+
+/// The git operations a pack needs. Paths given to the methods are directories inside a
+/// working tree (or, for `clone_repo`, the clone target); results are relative to them
+/// unless noted otherwise.
+pub trait GitBackend: Send + Sync {
+    fn is_repo(&self, path: &Path) -> bool;
+
+    /// A shallow clone of `url` into `target`, with a detached HEAD unless a branch was
+    /// asked for.
+    fn clone_repo(&self, url: &str, target: &Path, options: &CloneOptions) -> Result<()>;
+
+    /// Brings a clone made by `clone_repo` up to date with the revision `options` ask for.
+    fn update_clone(&self, url: &str, target: &Path, options: &CloneOptions) -> Result<()>;
+
+    /// Diffs split per file. Untracked files are included as additions whenever the
+    /// working tree is part of the comparison.
+    fn diffs(&self, path: &Path, options: &DiffOptions) -> Result<Vec<FileDiff>>;
+
+    /// The repository's default branch: what `origin/HEAD` points at, else a local `main`
+    /// or `master`.
+    fn default_branch(&self, path: &Path) -> Option<String>;
+
+    /// The full hash of the commit `rev` names.
+    fn resolve_commit(&self, path: &Path, rev: &str) -> Result<String>;
+
+    /// The full hash of the best common ancestor of `a` and `b`.
+    fn merge_base(&self, path: &Path, a: &str, b: &str) -> Result<String>;
+
+    /// How many commits `range` (`a..b`) selects.
+    fn count_commits(&self, path: &Path, range: &str) -> Result<usize>;
+
+    /// Commits as selected by `options`, newest first unless `options.reverse`. File paths
+    /// are relative to `path`; files outside it are left out.
+    fn log(&self, path: &Path, options: &LogOptions) -> Result<Vec<Commit>>;
+
+    /// How often each file changed in the last `window` commits before `rev` (`--ref`),
+    /// or HEAD.
+    fn file_change_counts(
+        &self,
+        path: &Path,
+        rev: Option<&str>,
+        window: usize,
+    ) -> Result<HashMap<String, usize>>;
+
+    /// Last commit of each of `files`, from a single walk of the history before `rev`, or
+    /// HEAD. The walk ends once every file tracked at `rev` has been found.
+    fn last_commits(
+        &self,
+        path: &Path,
+        rev: Option<&str>,
+        files: &[String],
+    ) -> Result<HashMap<String, LastCommit>>;
+
+    /// The last `count` commits that changed `file` (relative to `path`) up to `rev`, or
+    /// HEAD, and the lines of the file as packed (the working tree, or `rev`) that those
+    /// commits were the last to change.
+    fn file_history(
+        &self,
+        path: &Path,
+        file: &str,
+        rev: Option<&str>,
+        count: usize,
+    ) -> Result<FileHistory>;
+
+    /// Regular files and directories of `rev` below `path`, recursively and parents first.
+    /// Symlinks and submodules are skipped, as in a filesystem walk.
+    fn ls_tree(&self, path: &Path, rev: &str) -> Result<Vec<TreeEntry>>;
+
+    /// Contents of the given blobs, in order.
+    fn read_blobs(&self, path: &Path, objects: &[&str]) -> Result<Vec<Vec<u8>>>;
+
+    /// Paths that differ between the merge base of `base` and HEAD and the working tree:
+    /// committed on the branch, staged, unstaged and untracked. Without a base (and no
+    /// default branch) only uncommitted changes count. Deleted files are left out.
+    fn changed_paths(&self, path: &Path, base: Option<&str>) -> Result<Vec<String>> {
+        let base = base
+            .map(str::to_string)
+            .or_else(|| self.default_branch(path));
+        let options = DiffOptions {
+            base,
+            ..Default::default()
+        };
+        let mut paths: Vec<String> = self
+            .diffs(path, &options)?
+            .into_iter()
+            .filter(|d| d.status != "deleted")
+            .map(|d| d.path)
+            .collect();
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
 }
 
-pub fn clone_repo(url: &str, target: &Path, branch: Option<&str>) -> Result<()> {
-    let mut cmd = Command::new("git");
-    cmd.arg("clone").arg("--depth").arg("1");
+/// The backend `--git-backend` asks for. `auto` uses the built-in implementation and
+/// falls back to the `git` executable, when there is one, for anything it cannot do.
+pub fn backend(kind: GitBackendKind) -> Box<dyn GitBackend> {
+    match kind {
+        GitBackendKind::Native => Box::new(NativeBackend),
+        GitBackendKind::Cli => Box::new(CliBackend),
+        GitBackendKind::Auto => Box::new(AutoBackend {
+            cli: CliBackend::available().then_some(CliBackend),
+        }),
+    }
+}
+
+struct AutoBackend {
+    cli: Option<CliBackend>,
+}
 
-    if let Some(b) = branch {
-        cmd.arg("--branch").arg(b);
+impl AutoBackend {
+    fn or_cli<T>(
+        &self,
+        native: Result<T>,
+        cli: impl FnOnce(&CliBackend) -> Result<T>,
+    ) -> Result<T> {
+        match (native, &self.cli) {
+            (Err(native), Some(backend)) => cli(backend).map_err(|e| {
+                e.context(format!(
+                    "The git executable failed after the built-in backend did ({:#})",
+                    native
+                ))
+            }),
+            (result, _) => result,
+        }
     }
+}
 
-    cmd.arg(url).arg(target);
+impl GitBackend for AutoBackend {
+    fn is_repo(&self, path: &Path) -> bool {
+        NativeBackend.is_repo(path) || self.cli.as_ref().is_some_and(|c| c.is_repo(path))
+    }
+
+    fn clone_repo(&self, url: &str, target: &Path, options: &CloneOptions) -> Result<()> {
+        let native = NativeBackend.clone_repo(url, target, options);
+        if native.is_err() && target.exists() {
+            // Leave an empty target for the second attempt
+            std::fs::remove_dir_all(target)?;
+        }
+        self.or_cli(native, |c| c.clone_repo(url, target, options))
+    }
+
+    fn update_clone(&self, url: &str, target: &Path, options: &CloneOptions) -> Result<()> {
+        self.or_cli(NativeBackend.update_clone(url, target, options), |c| {
+            c.update_clone(url, target, options)
+        })
+    }
+
+    fn diffs(&self, path: &Path, options: &DiffOptions) -> Result<Vec<FileDiff>> {
+        self.or_cli(NativeBackend.diffs(path, options), |c| {
+            c.diffs(path, options)
+        })
+    }
+
+    fn default_branch(&self, path: &Path) -> Option<String> {
+        NativeBackend
+            .default_branch(path)
+            .or_else(|| self.cli.as_ref()?.default_branch(path))
+    }
+
+    fn resolve_commit(&self, path: &Path, rev: &str) -> Result<String> {
+        self.or_cli(NativeBackend.resolve_commit(path, rev), |c| {
+            c.resolve_commit(path, rev)
+        })
+    }
 
-    let status = cmd.status().context("Failed to execute git clone")?;
-    if !status.success() {
-        anyhow::bail!("Git clone failed");
+    fn merge_base(&self, path: &Path, a: &str, b: &str) -> Result<String> {
+        self.or_cli(NativeBackend.merge_base(path, a, b), |c| {
+            c.merge_base(path, a, b)
+        })
+    }
+
+    fn count_commits(&self, path: &Path, range: &str) -> Result<usize> {
+        self.or_cli(NativeBackend.count_commits(path, range), |c| {
+            c.count_commits(path, range)
+        })
+    }
+
+    fn log(&self, path: &Path, options: &LogOptions) -> Result<Vec<Commit>> {
+        self.or_cli(NativeBackend.log(path, options), |c| c.log(path, options))
+    }
+
+    fn file_change_counts(
+        &self,
+        path: &Path,
+        rev: Option<&str>,
+        window: usize,
+    ) -> Result<HashMap<String, usize>> {
+        self.or_cli(NativeBackend.file_change_counts(path, rev, window), |c| {
+            c.file_change_counts(path, rev, window)
+        })
+    }
+
+    fn last_commits(
+        &self,
+        path: &Path,
+        rev: Option<&str>,
+        files: &[String],
+    ) -> Result<HashMap<String, LastCommit>> {
+        self.or_cli(NativeBackend.last_commits(path, rev, files), |c| {
+            c.last_commits(path, rev, files)
+        })
+    }
+
+    fn file_history(
+        &self,
+        path: &Path,
+        file: &str,
+        rev: Option<&str>,
+        count: usize,
+    ) -> Result<FileHistory> {
+        self.or_cli(NativeBackend.file_history(path, file, rev, count), |c| {
+            c.file_history(path, file, rev, count)
+        })
+    }
+
+    fn ls_tree(&self, path: &Path, rev: &str) -> Result<Vec<TreeEntry>> {
+        self.or_cli(NativeBackend.ls_tree(path, rev), |c| c.ls_tree(path, rev))
+    }
+
+    fn read_blobs(&self, path: &Path, objects: &[&str]) -> Result<Vec<Vec<u8>>> {
+        self.or_cli(NativeBackend.read_blobs(path, objects), |c| {
+            c.read_blobs(path, objects)
+        })
+    }
+}
+
+/// Which commits `GitBackend::log` returns.
+#[derive(Debug, Default, Clone)]
+pub struct LogOptions {
+    /// Where to start (`--ref`), or an `a..b` range; HEAD when unset.
+    pub rev: Option<String>,
+    /// At most this many commits, counted after the other filters.
+    pub max_count: Option<usize>,
+    /// Only commits authored after this date (anything `git log --since` accepts).
+    pub since: Option<String>,
+    /// Only commits touching one of these paths; every commit when unset.
+    pub paths: Option<HashSet<String>>,
+    /// Attach each commit's diff, restricted to these paths.
+    pub patch_paths: Option<HashSet<String>>,
+    /// Oldest first.
+    pub reverse: bool,
+}
+
+/// One commit of the log.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Commit {
+    /// Abbreviated hash.
+    pub hash: String,
+    pub author: String,
+    pub email: String,
+    /// Author date, ISO 8601.
+    pub date: String,
+    pub subject: String,
+    /// The message after the subject line; empty for one-line messages.
+    pub body: String,
+    /// Files the commit added, changed or removed.
+    pub files: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<String>,
+}
+
+impl Commit {
+    /// Fills `subject` and `body` from a raw commit message.
+    fn set_message(&mut self, message: &str) {
+        let message = message.trim();
+        let (subject, body) = message.split_once('\n').unwrap_or((message, ""));
+        self.subject = subject.trim().to_string();
+        self.body = body.trim().to_string();
+    }
+}
+
+/// Concatenated patches of the files in `diffs` that are among `paths`.
+fn patch_for(diffs: Vec<FileDiff>, paths: &HashSet<String>) -> Option<String> {
+    let patch: String = diffs
+        .into_iter()
+        .filter(|d| paths.contains(&d.path))
+        .map(|d| d.patch)
+        .collect();
+    Some(patch).filter(|p| !p.is_empty())
+}
+
+/// Recent history of one file: who changed it lately and which lines.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileHistory {
+    pub path: String,
+    /// Newest first.
+    pub commits: Vec<FileCommit>,
+    /// Line ranges last changed by one of `commits`, by line number in the file as
+    /// committed or on disk, not in the packed content that compression or comment
+    /// removal may have reshaped. Older and uncommitted lines are not listed.
+    pub recent_lines: Vec<RecentLines>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileCommit {
+    pub hash: String,
+    /// Author date, `YYYY-MM-DD`.
+    pub date: String,
+    pub subject: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentLines {
+    pub start: usize,
+    pub end: usize,
+    pub hash: String,
+}
+
+/// Groups `(line, hash)` attributions into ranges of consecutive lines from one commit.
+fn recent_lines(mut lines: Vec<(usize, String)>) -> Vec<RecentLines> {
+    lines.sort();
+    let mut ranges: Vec<RecentLines> = Vec::new();
+    for (line, hash) in lines {
+        match ranges.last_mut() {
+            Some(last) if last.end + 1 == line && last.hash == hash => last.end = line,
+            _ => ranges.push(RecentLines {
+                start: line,
+                end: line,
+                hash,
+            }),
+        }
+    }
+    ranges
+}
+
+/// What `GitBackend::clone_repo` checks out.
+#[derive(Debug, Default, Clone)]
+pub struct CloneOptions {
+    /// Branch, tag or commit (full or abbreviated); the remote's HEAD when unset.
+    pub rev: Option<String>,
+    /// Only check out this directory (a sparse checkout).
+    pub sparse: Option<String>,
+}
+
+/// Which changes `GitBackend::diffs` reports.
+#[derive(Debug, Default, Clone)]
+pub struct DiffOptions {
+    /// Compare against the merge base of this ref and HEAD instead of HEAD.
+    pub base: Option<String>,
+    /// An explicit `a..b` (or `a...b`) range; the working tree is not consulted.
+    pub range: Option<String>,
+    /// Only changes staged in the index.
+    pub staged: bool,
+    /// Only working-tree changes not yet staged.
+    pub unstaged: bool,
+}
+
+/// The diff of one file.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileDiff {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_path: Option<String>,
+    /// `added`, `deleted`, `renamed` or `modified`.
+    pub status: &'static str,
+    pub additions: usize,
+    pub deletions: usize,
+    /// Whether the file is part of the pack, i.e. has its own file entry.
+    pub in_pack: bool,
+    pub patch: String,
+}
+
+impl FileDiff {
+    /// Line numbers on the new side of the diff that were added, or next to which lines
+    /// were removed.
+    pub fn changed_lines(&self) -> Vec<usize> {
+        let mut lines = Vec::new();
+        let mut next = None;
+        for line in self.patch.lines() {
+            if let Some(hunk) = line.strip_prefix("@@ ") {
+                // @@ -a,b +c,d @@ context
+                next = hunk
+                    .split_whitespace()
+                    .find_map(|field| field.strip_prefix('+'))
+                    .and_then(|range| range.split(',').next())
+                    .and_then(|start| start.parse::<usize>().ok());
+                continue;
+            }
+            let Some(n) = next.as_mut() else {
+                continue;
+            };
+            if line.starts_with('+') {
+                lines.push(*n);
+                *n += 1;
+            } else if line.starts_with('-') {
+                lines.push((*n).max(1));
+            } else if line.starts_with(' ') || line.is_empty() {
+                *n += 1;
+            }
+        }
+        lines.dedup();
+        lines
     }
-    Ok(())
 }
 
-pub fn get_diffs(path: &Path) -> Result<String> {
-    let output = Command::new("git")
-        .args(["diff", "HEAD"])
-        .current_dir(path)
-        .output()?;
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+/// A file or directory in a commit's tree.
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    /// Relative to the directory `ls_tree` was run in, `/`-separated.
+    pub path: String,
+    pub object: String,
+    pub is_dir: bool,
 }
 
-pub fn get_logs(path: &Path) -> Result<String> {
-    let output = Command::new("git")
-        .args(["log", "-n", "50", "--pretty=format:%h - %an, %ar : %s"])
-        .current_dir(path)
-        .output()?;
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+/// Those of `files` tracked at `rev` (or HEAD): the ones a walk of the history will find.
+fn tracked(
+    git: &dyn GitBackend,
+    path: &Path,
+    rev: Option<&str>,
+    files: &[String],
+) -> Result<HashSet<String>> {
+    let entries = git.ls_tree(path, rev.unwrap_or("HEAD"))?;
+    let tracked: HashSet<&str> = entries
+        .iter()
+        .filter(|e| !e.is_dir)
+        .map(|e| e.path.as_str())
+        .collect();
+    Ok(files
+        .iter()
+        .filter(|f| tracked.contains(f.as_str()))
+        .cloned()
+        .collect())
 }
 
-pub fn get_file_change_counts(path: &Path) -> HashMap<String, usize> {
-    let output = Command::new("git")
-        .args(["log", "--name-only", "--format=", "-n", "100"])
-        .current_dir(path)
-        .output();
+const SYMLINK_MODE: u32 = 0o120000;
 
-    let mut counts = HashMap::new();
-    if let Ok(out) = output {
-        let s = String::from_utf8_lossy(&out.stdout);
-        for line in s.lines() {
-            if !line.trim().is_empty() {
-                *counts.entry(line.trim().to_string()).or_insert(0) += 1;
+#[cfg(unix)]
+fn file_mode(meta: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    if meta.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+#[cfg(not(unix))]
+fn file_mode(_meta: &std::fs::Metadata) -> u32 {
+    0o100644
+}
+
+/// Git's heuristic: a NUL byte in the first 8000 bytes.
+fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+/// Splits unified diff output into one entry per `diff --git` section.
+fn split_diff(diff: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    // Extended header lines come before the first hunk of each file
+    let mut in_header = true;
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("diff --git ") || files.is_empty() {
+            in_header = true;
+            let header = line.trim_end().trim_start_matches("diff --git ");
+            let path = header
+                .rsplit_once(" b/")
+                .map(|(_, p)| p.to_string())
+                .unwrap_or_default();
+            files.push(FileDiff {
+                path,
+                old_path: None,
+                status: "modified",
+                additions: 0,
+                deletions: 0,
+                in_pack: false,
+                patch: String::new(),
+            });
+        }
+        let Some(file) = files.last_mut() else {
+            continue;
+        };
+        file.patch.push_str(line);
+
+        let text = line.trim_end_matches(['\n', '\r']);
+        if !in_header {
+            if text.starts_with('+') {
+                file.additions += 1;
+            } else if text.starts_with('-') {
+                file.deletions += 1;
+            }
+        } else if text.starts_with("@@") {
+            in_header = false;
+        } else if text.starts_with("new file mode") {
+            file.status = "added";
+        } else if text.starts_with("deleted file mode") {
+            file.status = "deleted";
+        } else if let Some(old) = text.strip_prefix("rename from ") {
+            file.status = "renamed";
+            file.old_path = Some(old.to_string());
+        } else if let Some(new) = text.strip_prefix("rename to ") {
+            file.path = new.to_string();
+        } else if let Some(new) = text.strip_prefix("+++ b/") {
+            file.path = new.to_string();
+        } else if let Some(old) = text.strip_prefix("--- a/") {
+            if file.status == "deleted" {
+                file.path = old.to_string();
             }
         }
     }
-    counts
+    files.retain(|f| !f.patch.trim().is_empty());
+    files
+}
+
+/// The most recent commit that touched a file.
+#[derive(Debug, Clone)]
+pub struct LastCommit {
+    pub hash: String,
+    pub date: String,
+    /// Author time in seconds since the epoch.
+    pub time: i64,
+    pub author: String,
 }
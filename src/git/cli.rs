@@ -0,0 +1,565 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use super::{
+    file_mode, is_binary, patch_for, recent_lines, split_diff, tracked, CloneOptions, Commit,
+    DiffOptions, FileCommit, FileDiff, FileHistory, GitBackend, LastCommit, LogOptions, TreeEntry,
+    SYMLINK_MODE,
+};
+
+/// Shells out to the `git` executable.
+pub struct CliBackend;
+
+impl CliBackend {
+    /// Whether a `git` executable is on the PATH.
+    pub fn available() -> bool {
+        Command::new("git")
+            .arg("--version")
+            .output()
+            .is_ok_and(|o| o.status.success())
+    }
+}
+
+impl GitBackend for CliBackend {
+    fn is_repo(&self, path: &Path) -> bool {
+        Command::new("git")
+            .arg("rev-parse")
+            .arg("--is-inside-work-tree")
+            .current_dir(path)
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    }
+
+    /// Fetches just the wanted commit, which unlike `git clone --branch` also works for
+    /// commit hashes. Blobs outside a sparse checkout are not fetched where the server
+    /// supports filters.
+    fn clone_repo(&self, url: &str, target: &Path, options: &CloneOptions) -> Result<()> {
+        fs::create_dir_all(target)?;
+        run_git(target, &["init", "--quiet"])?;
+        run_git(target, &["remote", "add", "origin", url])?;
+        if let Some(dir) = &options.sparse {
+            run_git(target, &["sparse-checkout", "set", dir])?;
+        }
+        self.update_clone(url, target, options)
+    }
+
+    /// Fetches the revision again (only new objects are transferred) and checks it out.
+    fn update_clone(&self, url: &str, target: &Path, options: &CloneOptions) -> Result<()> {
+        let filter = options.sparse.as_ref().map(|_| "--filter=blob:none");
+
+        let rev = options.rev.as_deref().unwrap_or("HEAD");
+        let mut fetch = vec!["fetch", "--quiet", "--depth", "1"];
+        fetch.extend(filter);
+        fetch.extend(["origin", rev]);
+        let checkout = match run_git(target, &fetch) {
+            Ok(_) => "FETCH_HEAD",
+            Err(e) if options.rev.is_none() => return Err(e),
+            Err(e) if !is_abbreviated_hash(rev) => {
+                return Err(e.context(format!("Remote revision {} not found in {}", rev, url)))
+            }
+            Err(_) => {
+                // An abbreviated commit cannot be fetched by name: fetch all, then resolve it
+                let mut fetch = vec!["fetch", "--quiet", "--tags"];
+                fetch.extend(filter);
+                fetch.extend(["origin", "+refs/heads/*:refs/remotes/origin/*"]);
+                run_git(target, &fetch)?;
+                rev
+            }
+        };
+        run_git(target, &["checkout", "--quiet", "--force", checkout])
+            .with_context(|| format!("Remote revision {} not found in {}", rev, url))?;
+        Ok(())
+    }
+
+    fn diffs(&self, path: &Path, options: &DiffOptions) -> Result<Vec<FileDiff>> {
+        let mut args = vec![
+            "-c",
+            "core.quotePath=false",
+            "diff",
+            "--relative",
+            "--no-color",
+            "--no-ext-diff",
+        ];
+        let merge_base;
+        if let Some(range) = &options.range {
+            args.push(range);
+        } else {
+            let base = match &options.base {
+                Some(base) => {
+                    merge_base = run_git(path, &["merge-base", base, "HEAD"])?
+                        .trim()
+                        .to_string();
+                    merge_base.as_str()
+                }
+                None => "HEAD",
+            };
+            if options.staged {
+                args.extend(["--cached", base]);
+            } else if !options.unstaged {
+                args.push(base);
+            }
+        }
+        let mut diff = run_git(path, &args)?;
+
+        if options.range.is_none() && !options.staged {
+            let untracked = run_git(path, &["ls-files", "-z", "--others", "--exclude-standard"])?;
+            for file in untracked.split('\0').filter(|f| !f.is_empty()) {
+                untracked_patch(path, file, &mut diff)?;
+            }
+        }
+        Ok(split_diff(&diff))
+    }
+
+    fn default_branch(&self, path: &Path) -> Option<String> {
+        if let Ok(head) = run_git(
+            path,
+            &[
+                "symbolic-ref",
+                "--quiet",
+                "--short",
+                "refs/remotes/origin/HEAD",
+            ],
+        ) {
+            return Some(head.trim().to_string());
+        }
+        ["main", "master"]
+            .into_iter()
+            .find(|b| run_git(path, &["rev-parse", "--verify", "--quiet", b]).is_ok())
+            .map(str::to_string)
+    }
+
+    fn resolve_commit(&self, path: &Path, rev: &str) -> Result<String> {
+        let spec = format!("{}^{{commit}}", rev);
+        Ok(run_git(path, &["rev-parse", "--verify", "--quiet", &spec])
+            .with_context(|| format!("Unknown revision {:?}", rev))?
+            .trim()
+            .to_string())
+    }
+
+    fn merge_base(&self, path: &Path, a: &str, b: &str) -> Result<String> {
+        Ok(run_git(path, &["merge-base", a, b])?.trim().to_string())
+    }
+
+    fn count_commits(&self, path: &Path, range: &str) -> Result<usize> {
+        Ok(run_git(path, &["rev-list", "--count", range])?
+            .trim()
+            .parse()
+            .unwrap_or(0))
+    }
+
+    fn log(&self, path: &Path, options: &LogOptions) -> Result<Vec<Commit>> {
+        // Fields are separated by 0x1f, the message by 0x1e from the file names
+        let mut args: Vec<String> = [
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--relative",
+            "--name-only",
+            "--no-color",
+            "--format=%x00%h%x1f%an%x1f%ae%x1f%aI%x1f%B%x1e",
+        ]
+        .map(String::from)
+        .to_vec();
+        if let Some(count) = options.max_count {
+            args.push(format!("--max-count={}", count));
+        }
+        if let Some(since) = &options.since {
+            args.push(format!("--since={}", since));
+        }
+        if options.reverse {
+            args.push("--reverse".to_string());
+        }
+        args.extend(options.rev.clone());
+        let log = match &options.paths {
+            None => {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                run_git(path, &args)?
+            }
+            Some(paths) if paths.is_empty() => return Ok(Vec::new()),
+            Some(paths) => {
+                // Pathspecs go through stdin so large focus sets stay below
+                // the argument limit; a matching commit lists all its files
+                args.insert(0, "--literal-pathspecs".to_string());
+                args.extend(["--full-diff".to_string(), "--stdin".to_string()]);
+                let mut input = String::from("--\n");
+                for p in paths.iter().filter(|p| !p.contains('\n')) {
+                    input.push_str(p);
+                    input.push('\n');
+                }
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                run_git_with_input(path, &args, input.into_bytes())?
+            }
+        };
+
+        let mut commits = Vec::new();
+        for record in log.split('\0').filter(|r| !r.trim().is_empty()) {
+            let (header, files) = record.split_once('\x1e').unwrap_or((record, ""));
+            let mut fields = header.splitn(5, '\x1f');
+            let mut commit = Commit {
+                hash: fields.next().unwrap_or_default().to_string(),
+                author: fields.next().unwrap_or_default().to_string(),
+                email: fields.next().unwrap_or_default().to_string(),
+                date: fields.next().unwrap_or_default().to_string(),
+                subject: String::new(),
+                body: String::new(),
+                files: files
+                    .lines()
+                    .filter(|l| !l.trim().is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                patch: None,
+            };
+            commit.set_message(fields.next().unwrap_or_default());
+            if let Some(paths) = &options.patch_paths {
+                let diff = run_git(
+                    path,
+                    &[
+                        "-c",
+                        "core.quotePath=false",
+                        "diff-tree",
+                        "-p",
+                        "-M",
+                        "--root",
+                        "--relative",
+                        "--no-color",
+                        "--no-ext-diff",
+                        &commit.hash,
+                    ],
+                )?;
+                commit.patch = patch_for(split_diff(&diff), paths);
+            }
+            commits.push(commit);
+        }
+        Ok(commits)
+    }
+
+    fn file_change_counts(
+        &self,
+        path: &Path,
+        rev: Option<&str>,
+        window: usize,
+    ) -> Result<HashMap<String, usize>> {
+        let max_count = format!("--max-count={}", window);
+        let mut args = vec![
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--relative",
+            "--name-only",
+            "--format=",
+            &max_count,
+        ];
+        args.extend(rev);
+        let log = run_git(path, &args)?;
+
+        let mut counts = HashMap::new();
+        for line in log.lines() {
+            if !line.trim().is_empty() {
+                *counts.entry(line.trim().to_string()).or_insert(0) += 1;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Reads `git log` as it runs, and stops it once every tracked file is found.
+    fn last_commits(
+        &self,
+        path: &Path,
+        rev: Option<&str>,
+        files: &[String],
+    ) -> Result<HashMap<String, LastCommit>> {
+        let mut pending = tracked(self, path, rev, files)?;
+        let mut commits = HashMap::new();
+        if pending.is_empty() {
+            return Ok(commits);
+        }
+        let mut args = vec![
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--relative",
+            "--name-only",
+            "--format=%x00%h%x09%as%x09%at%x09%an",
+        ];
+        args.extend(rev);
+        let mut child = Command::new("git")
+            .args(&args)
+            .current_dir(path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to execute git")?;
+        let stdout = child.stdout.take().context("git log has no stdout")?;
+
+        let mut current: Option<LastCommit> = None;
+        for line in BufReader::new(stdout).split(b'\n') {
+            let line = String::from_utf8_lossy(&line?).to_string();
+            if let Some(header) = line.strip_prefix('\0') {
+                let mut fields = header.splitn(4, '\t');
+                current = Some(LastCommit {
+                    hash: fields.next().unwrap_or_default().to_string(),
+                    date: fields.next().unwrap_or_default().to_string(),
+                    time: fields
+                        .next()
+                        .unwrap_or_default()
+                        .parse()
+                        .unwrap_or_default(),
+                    author: fields.next().unwrap_or_default().to_string(),
+                });
+            } else if let Some(commit) = &current {
+                if pending.remove(line.trim()) {
+                    commits.insert(line.trim().to_string(), commit.clone());
+                    if pending.is_empty() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if pending.is_empty() {
+            // The rest of the history is not needed
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(commits);
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "git log failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(commits)
+    }
+
+    fn file_history(
+        &self,
+        path: &Path,
+        file: &str,
+        rev: Option<&str>,
+        count: usize,
+    ) -> Result<FileHistory> {
+        let max_count = format!("--max-count={}", count);
+        let mut args = vec!["log", &max_count, "--format=%H%x1f%h%x1f%as%x1f%s"];
+        args.extend(rev);
+        args.extend(["--", file]);
+        let log = run_git(path, &args)?;
+        let mut full_hashes = HashMap::new();
+        let mut commits = Vec::new();
+        for line in log.lines() {
+            let mut fields = line.splitn(4, '\x1f');
+            let (Some(full), Some(hash), Some(date), Some(subject)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            full_hashes.insert(full.to_string(), hash.to_string());
+            commits.push(FileCommit {
+                hash: hash.to_string(),
+                date: date.to_string(),
+                subject: subject.to_string(),
+            });
+        }
+
+        let mut lines = Vec::new();
+        if !commits.is_empty() {
+            let mut args = vec!["blame", "--porcelain"];
+            args.extend(rev);
+            args.extend(["--", file]);
+            // Each line starts with "<hash> <original line> <final line>[ <group size>]"
+            for line in run_git(path, &args)?.lines() {
+                let mut fields = line.split(' ');
+                let (Some(hash), Some(_), Some(final_line)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    continue;
+                };
+                if let (Some(short), Ok(n)) = (full_hashes.get(hash), final_line.parse()) {
+                    lines.push((n, short.clone()));
+                }
+            }
+        }
+        Ok(FileHistory {
+            path: file.to_string(),
+            commits,
+            recent_lines: recent_lines(lines),
+        })
+    }
+
+    fn ls_tree(&self, path: &Path, rev: &str) -> Result<Vec<TreeEntry>> {
+        let spec = format!("{}^{{tree}}", rev);
+        let listing = run_git(path, &["ls-tree", "-r", "-t", "-z", &spec])?;
+        let mut entries = Vec::new();
+        for record in listing.split('\0').filter(|r| !r.is_empty()) {
+            // <mode> SP <type> SP <object> TAB <path>
+            let Some((meta, file)) = record.split_once('\t') else {
+                continue;
+            };
+            let mut fields = meta.split(' ');
+            let (Some(mode), Some(kind), Some(object)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let is_dir = kind == "tree";
+            if !is_dir && (kind != "blob" || mode == "120000") {
+                continue;
+            }
+            entries.push(TreeEntry {
+                path: file.to_string(),
+                object: object.to_string(),
+                is_dir,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Reads every blob through one `git cat-file --batch` process.
+    fn read_blobs(&self, path: &Path, objects: &[&str]) -> Result<Vec<Vec<u8>>> {
+        let mut child = Command::new("git")
+            .args(["cat-file", "--batch"])
+            .current_dir(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to execute git cat-file")?;
+
+        // Feed the requests from another thread so a full stdout pipe cannot block us
+        let mut stdin = child.stdin.take().context("git cat-file has no stdin")?;
+        let requests: String = objects.iter().map(|o| format!("{}\n", o)).collect();
+        let writer = std::thread::spawn(move || stdin.write_all(requests.as_bytes()));
+        let output = child.wait_with_output()?;
+        writer
+            .join()
+            .map_err(|_| anyhow::anyhow!("git cat-file writer panicked"))??;
+        if !output.status.success() {
+            anyhow::bail!("git cat-file --batch failed");
+        }
+
+        // Each reply is "<object> <type> <size>\n<content>\n"
+        let mut blobs = Vec::with_capacity(objects.len());
+        let mut rest = output.stdout.as_slice();
+        for object in objects {
+            let header_end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .context("Truncated git cat-file output")?;
+            let header = String::from_utf8_lossy(&rest[..header_end]).to_string();
+            let size: usize = match header.rsplit(' ').next().map(str::parse) {
+                Some(Ok(size)) if !header.ends_with(" missing") => size,
+                _ => anyhow::bail!("Cannot read object {}: {}", object, header),
+            };
+            let start = header_end + 1;
+            let content = rest
+                .get(start..start + size)
+                .context("Truncated git cat-file output")?;
+            blobs.push(content.to_vec());
+            rest = rest.get(start + size + 1..).unwrap_or_default();
+        }
+        Ok(blobs)
+    }
+}
+
+/// Appends the patch adding the untracked `file`, in `git diff` layout. `git diff` only
+/// compares tracked files, so the patch is built here; git only hashes the contents.
+fn untracked_patch(path: &Path, file: &str, out: &mut String) -> Result<()> {
+    let full = path.join(file);
+    let Ok(meta) = fs::symlink_metadata(&full) else {
+        return Ok(());
+    };
+    let (data, mode) = if meta.file_type().is_symlink() {
+        let target = fs::read_link(&full)?;
+        (target.to_string_lossy().as_bytes().to_vec(), SYMLINK_MODE)
+    } else if meta.is_file() {
+        (fs::read(&full)?, file_mode(&meta))
+    } else {
+        return Ok(());
+    };
+
+    let id = run_git_with_input(path, &["hash-object", "--stdin"], data.clone())?;
+    let id = id.trim();
+
+    out.push_str(&format!(
+        "diff --git a/{file} b/{file}\nnew file mode {:o}\nindex {}..{}\n",
+        mode,
+        "0".repeat(7),
+        &id[..id.len().min(7)]
+    ));
+    if data.is_empty() {
+        return Ok(());
+    }
+    if is_binary(&data) {
+        out.push_str(&format!("Binary files /dev/null and b/{} differ\n", file));
+        return Ok(());
+    }
+    let text = String::from_utf8_lossy(&data);
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    out.push_str("--- /dev/null\n");
+    out.push_str(&format!("+++ b/{}\n", file));
+    if lines.len() == 1 {
+        out.push_str("@@ -0,0 +1 @@\n");
+    } else {
+        out.push_str(&format!("@@ -0,0 +1,{} @@\n", lines.len()));
+    }
+    for line in lines {
+        out.push('+');
+        out.push_str(line);
+        if !line.ends_with('\n') {
+            out.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+    Ok(())
+}
+
+/// Like [`run_git`], writing `input` to the command's stdin.
+fn run_git_with_input(path: &Path, args: &[&str], input: Vec<u8>) -> Result<String> {
+    let mut child = Command::new("git")
+        .args(args)
+        .current_dir(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute git")?;
+    let mut stdin = child.stdin.take().context("git has no stdin")?;
+    // Write from a thread so a full stdout pipe cannot deadlock the child
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("Writing to git {} failed", args.join(" ")))??;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Whether `rev` could be a commit hash too short to fetch by name.
+fn is_abbreviated_hash(rev: &str) -> bool {
+    (4..40).contains(&rev.len()) && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn run_git(path: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(path)
+        .output()
+        .context("Failed to execute git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
@@ -0,0 +1,939 @@
+use anyhow::{Context, Result};
+use gix::diff::blob::intern::InternedInput;
+use gix::diff::blob::unified_diff::{ConsumeHunk, ContextSize, DiffLineKind, HunkHeader};
+use gix::diff::blob::{sources::byte_lines_with_terminator, Algorithm, UnifiedDiff};
+use gix::revision::walk::Sorting;
+use gix::traverse::commit::simple::CommitTimeOrder;
+use gix::{ObjectId, Repository};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::{
+    file_mode, is_binary, patch_for, recent_lines, split_diff, tracked, CloneOptions, Commit,
+    DiffOptions, FileCommit, FileDiff, FileHistory, GitBackend, LastCommit, LogOptions, TreeEntry,
+    SYMLINK_MODE,
+};
+
+/// Reads repositories in-process through gitoxide, without a `git` executable.
+pub struct NativeBackend;
+
+/// One side of a diff: file paths (relative to the directory asked about) and their
+/// versions.
+type Side = BTreeMap<String, Version>;
+
+struct Version {
+    id: ObjectId,
+    mode: u32,
+    /// Contents read from the working tree; blobs are loaded on demand.
+    data: Option<Vec<u8>>,
+}
+
+const SUBMODULE_MODE: u32 = 0o160000;
+
+impl GitBackend for NativeBackend {
+    fn is_repo(&self, path: &Path) -> bool {
+        gix::discover(path).is_ok_and(|r| r.workdir().is_some())
+    }
+
+    /// Only local repositories (a path or a `file://` URL) can be cloned. The clone
+    /// borrows the source's objects through `objects/info/alternates` instead of copying
+    /// them.
+    fn clone_repo(&self, url: &str, target: &Path, options: &CloneOptions) -> Result<()> {
+        let source_path = local_source(url)?;
+        let source =
+            gix::open(source_path).with_context(|| format!("Not a git repository: {}", url))?;
+        let (commit_id, branch_name) = match options.rev.as_deref() {
+            Some(rev) => {
+                if let Ok(mut reference) =
+                    source.find_reference(format!("refs/heads/{}", rev).as_str())
+                {
+                    (reference.peel_to_commit()?.id, Some(rev.to_string()))
+                } else {
+                    // A tag or a (possibly abbreviated) commit
+                    let commit = source
+                        .rev_parse_single(rev)
+                        .ok()
+                        .and_then(|id| id.object().ok()?.peel_to_commit().ok())
+                        .with_context(|| format!("Remote revision {} not found in {}", rev, url))?;
+                    (commit.id, None)
+                }
+            }
+            None => {
+                let name = source.head_name()?.map(|n| n.shorten().to_string());
+                (source.head_commit()?.id, name)
+            }
+        };
+        let objects = fs::canonicalize(source.common_dir().join("objects"))?;
+
+        let repo = gix::init(target)?;
+        let info = repo.git_dir().join("objects").join("info");
+        fs::create_dir_all(&info)?;
+        fs::write(info.join("alternates"), format!("{}\n", objects.display()))?;
+        // Reopen so the object store picks up the alternate
+        let repo = gix::open(target)?;
+
+        let head = match &branch_name {
+            Some(name) => {
+                // Written directly: a reference edit would want a committer for the reflog
+                let reference = format!("refs/heads/{}", name);
+                let file = repo.git_dir().join(&reference);
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(file, format!("{}\n", commit_id))?;
+                format!("ref: {}\n", reference)
+            }
+            None => format!("{}\n", commit_id),
+        };
+        fs::write(repo.git_dir().join("HEAD"), head)?;
+
+        let tree_id = repo.find_commit(commit_id)?.tree_id()?.detach();
+        let sparse = options.sparse.as_deref();
+        checkout(&repo, tree_id, target, sparse)?;
+        let mut index = repo.index_from_tree(&tree_id)?;
+        if let Some(dir) = sparse {
+            // Mark what was left out, as `git sparse-checkout` does
+            for (entry, path) in index.entries_mut_with_paths() {
+                if relative(dir, &path.to_string()).is_none() {
+                    entry.flags |= gix::index::entry::Flags::SKIP_WORKTREE
+                        | gix::index::entry::Flags::EXTENDED;
+                }
+            }
+        }
+        index.write(Default::default())?;
+        Ok(())
+    }
+
+    /// Clones again beside `target`, which is replaced only once the clone succeeded.
+    fn update_clone(&self, url: &str, target: &Path, options: &CloneOptions) -> Result<()> {
+        local_source(url)?;
+        let fresh = target.with_extension("update");
+        if fresh.exists() {
+            fs::remove_dir_all(&fresh)?;
+        }
+        if let Err(e) = self.clone_repo(url, &fresh, options) {
+            let _ = fs::remove_dir_all(&fresh);
+            return Err(e);
+        }
+        fs::remove_dir_all(target)?;
+        fs::rename(&fresh, target)?;
+        Ok(())
+    }
+
+    fn diffs(&self, path: &Path, options: &DiffOptions) -> Result<Vec<FileDiff>> {
+        let (repo, prefix) = open(path)?;
+        let (old, new) = if let Some(range) = &options.range {
+            if let Some((a, b)) = range.split_once("...") {
+                let head = commit_id(&repo, b)?;
+                let base = repo.merge_base(commit_id(&repo, a)?, head)?.detach();
+                (
+                    tree_side(&repo, &prefix, base)?,
+                    tree_side(&repo, &prefix, head)?,
+                )
+            } else if let Some((a, b)) = range.split_once("..") {
+                let old = tree_side(&repo, &prefix, commit_id(&repo, a)?)?;
+                (old, tree_side(&repo, &prefix, commit_id(&repo, b)?)?)
+            } else {
+                let old = tree_side(&repo, &prefix, commit_id(&repo, range)?)?;
+                let index = index_side(&repo, &prefix)?;
+                (old, worktree_side(&repo, &prefix, &index)?)
+            }
+        } else {
+            let index = index_side(&repo, &prefix)?;
+            let base = match &options.base {
+                Some(base) => {
+                    let head = commit_id(&repo, "HEAD")?;
+                    let merge_base = repo.merge_base(commit_id(&repo, base)?, head)?;
+                    tree_side(&repo, &prefix, merge_base.detach())?
+                }
+                None => match repo.head_id() {
+                    Ok(head) => tree_side(&repo, &prefix, head.detach())?,
+                    // Nothing committed yet
+                    Err(_) => Side::new(),
+                },
+            };
+            if options.staged {
+                (base, index)
+            } else {
+                let worktree = worktree_side(&repo, &prefix, &index)?;
+                if options.unstaged {
+                    (index, worktree)
+                } else {
+                    (base, worktree)
+                }
+            }
+        };
+
+        let mut diff = String::new();
+        diff_sides(&repo, &old, &new, &mut diff)?;
+        if options.range.is_none() && !options.staged {
+            let index = index_side(&repo, &prefix)?;
+            for (file, version) in untracked(&repo, &prefix, &index)? {
+                write_patch(&repo, &mut diff, None, &file, None, Some(&version))?;
+            }
+        }
+        Ok(split_diff(&diff))
+    }
+
+    fn default_branch(&self, path: &Path) -> Option<String> {
+        let repo = gix::discover(path).ok()?;
+        if let Ok(head) = repo.find_reference("refs/remotes/origin/HEAD") {
+            if let gix::refs::TargetRef::Symbolic(name) = head.target() {
+                return Some(name.shorten().to_string());
+            }
+        }
+        ["main", "master"]
+            .into_iter()
+            .find(|b| repo.rev_parse_single(*b).is_ok())
+            .map(str::to_string)
+    }
+
+    fn resolve_commit(&self, path: &Path, rev: &str) -> Result<String> {
+        let (repo, _) = open(path)?;
+        Ok(commit_id(&repo, rev)?.to_string())
+    }
+
+    fn merge_base(&self, path: &Path, a: &str, b: &str) -> Result<String> {
+        let (repo, _) = open(path)?;
+        let base = repo.merge_base(commit_id(&repo, a)?, commit_id(&repo, b)?)?;
+        Ok(base.to_string())
+    }
+
+    fn count_commits(&self, path: &Path, range: &str) -> Result<usize> {
+        let (repo, _) = open(path)?;
+        let mut count = 0;
+        for info in walk(&repo, Some(range), None)? {
+            info?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn log(&self, path: &Path, options: &LogOptions) -> Result<Vec<Commit>> {
+        let (repo, prefix) = open(path)?;
+        let since = match &options.since {
+            Some(since) => Some(
+                gix::date::parse(since, Some(SystemTime::now()))
+                    .with_context(|| format!("Cannot parse date {:?}", since))?
+                    .seconds,
+            ),
+            None => None,
+        };
+        let mut commits = Vec::new();
+        for info in walk(&repo, options.rev.as_deref(), since)? {
+            if options.max_count.is_some_and(|max| commits.len() >= max) {
+                break;
+            }
+            let id = info?.id;
+            let files: Vec<String> = changed_files(&repo, id)?
+                .iter()
+                .filter_map(|f| relative(&prefix, f))
+                .map(str::to_string)
+                .collect();
+            if let Some(paths) = &options.paths {
+                if !files.iter().any(|f| paths.contains(f)) {
+                    continue;
+                }
+            }
+            let object = repo.find_commit(id)?;
+            let author = object.author()?;
+            let mut commit = Commit {
+                hash: object.id().shorten_or_id().to_string(),
+                author: author.name.to_string().trim().to_string(),
+                email: author.email.to_string().trim().to_string(),
+                date: author
+                    .time()?
+                    .format(gix::date::time::format::ISO8601_STRICT),
+                subject: String::new(),
+                body: String::new(),
+                files,
+                patch: None,
+            };
+            commit.set_message(&object.message_raw()?.to_string());
+            if let Some(paths) = &options.patch_paths {
+                commit.patch = commit_patch(&repo, &prefix, &object, paths)?;
+            }
+            commits.push(commit);
+        }
+        if options.reverse {
+            commits.reverse();
+        }
+        Ok(commits)
+    }
+
+    fn file_change_counts(
+        &self,
+        path: &Path,
+        rev: Option<&str>,
+        window: usize,
+    ) -> Result<HashMap<String, usize>> {
+        let (repo, prefix) = open(path)?;
+        let mut counts = HashMap::new();
+        for info in walk(&repo, rev, None)?.take(window) {
+            for file in changed_files(&repo, info?.id)? {
+                if let Some(file) = relative(&prefix, &file) {
+                    *counts.entry(file.to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok(counts)
+    }
+
+    fn last_commits(
+        &self,
+        path: &Path,
+        rev: Option<&str>,
+        files: &[String],
+    ) -> Result<HashMap<String, LastCommit>> {
+        let mut pending = tracked(self, path, rev, files)?;
+        let (repo, prefix) = open(path)?;
+        let mut commits = HashMap::new();
+        for info in walk(&repo, rev, None)? {
+            if pending.is_empty() {
+                break;
+            }
+            let info = info?;
+            let changed = changed_files(&repo, info.id)?;
+            if changed.is_empty() {
+                continue;
+            }
+            let commit = info.object()?;
+            let author = commit.author()?;
+            let time = author.time()?;
+            let last = LastCommit {
+                hash: commit.id().shorten_or_id().to_string(),
+                date: time.format(gix::date::time::format::SHORT),
+                time: time.seconds,
+                author: author.name.to_string().trim().to_string(),
+            };
+            for file in changed {
+                if let Some(file) = relative(&prefix, &file) {
+                    if pending.remove(file) {
+                        commits.insert(file.to_string(), last.clone());
+                    }
+                }
+            }
+        }
+        Ok(commits)
+    }
+
+    /// Follows first parents only, and stops at a rename.
+    fn file_history(
+        &self,
+        path: &Path,
+        file: &str,
+        rev: Option<&str>,
+        count: usize,
+    ) -> Result<FileHistory> {
+        let (repo, prefix) = open(path)?;
+        let full = if prefix.is_empty() {
+            file.to_string()
+        } else {
+            format!("{}/{}", prefix, file)
+        };
+        let mut commit = repo.find_commit(commit_id(&repo, rev.unwrap_or("HEAD"))?)?;
+        let mut blob = blob_at(&commit, &full)?;
+        let mut data = match blob {
+            Some(id) => repo.find_object(id)?.detach().data,
+            None => Vec::new(),
+        };
+
+        // Lines still to attribute: (line number on disk, line index in `data`)
+        let mut pending: Vec<(usize, usize)> = match rev {
+            Some(_) => (0..line_count(&data)).map(|i| (i + 1, i)).collect(),
+            None => {
+                let disk = fs::read(workdir(&repo, &prefix)?.join(file))?;
+                // Lines not in the last commit are uncommitted and never attributed
+                line_origins(&data, &disk)
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, origin)| origin.map(|o| (i + 1, o)))
+                    .collect()
+            }
+        };
+
+        let mut commits = Vec::new();
+        let mut lines = Vec::new();
+        while blob.is_some() && commits.len() < count {
+            let parent = match commit.parent_ids().next() {
+                Some(id) => Some(repo.find_commit(id)?),
+                None => None,
+            };
+            let parent_blob = match &parent {
+                Some(parent) => blob_at(parent, &full)?,
+                None => None,
+            };
+            let parent_data = match parent_blob {
+                Some(id) if Some(id) != blob => repo.find_object(id)?.detach().data,
+                _ => Vec::new(),
+            };
+            if parent_blob != blob {
+                let author = commit.author()?;
+                let hash = commit.id().shorten_or_id().to_string();
+                commits.push(FileCommit {
+                    hash: hash.clone(),
+                    date: author.time()?.format(gix::date::time::format::SHORT),
+                    subject: commit.message()?.summary().to_string(),
+                });
+                let origins = line_origins(&parent_data, &data);
+                pending.retain_mut(
+                    |(line, index)| match origins.get(*index).copied().flatten() {
+                        Some(origin) => {
+                            *index = origin;
+                            true
+                        }
+                        None => {
+                            lines.push((*line, hash.clone()));
+                            false
+                        }
+                    },
+                );
+                data = parent_data;
+            }
+            let Some(parent) = parent else {
+                break;
+            };
+            commit = parent;
+            blob = parent_blob;
+        }
+        Ok(FileHistory {
+            path: file.to_string(),
+            commits,
+            recent_lines: recent_lines(lines),
+        })
+    }
+
+    fn ls_tree(&self, path: &Path, rev: &str) -> Result<Vec<TreeEntry>> {
+        let (repo, prefix) = open(path)?;
+        let tree = repo.find_commit(commit_id(&repo, rev)?)?.tree()?;
+        let mut entries = Vec::new();
+        for entry in tree.traverse().breadthfirst.files()? {
+            let full = entry.filepath.to_string();
+            let Some(file) = relative(&prefix, &full).filter(|f| !f.is_empty()) else {
+                continue;
+            };
+            if entry.mode.is_link() || entry.mode.is_commit() {
+                continue;
+            }
+            entries.push(TreeEntry {
+                path: file.to_string(),
+                object: entry.oid.to_string(),
+                is_dir: entry.mode.is_tree(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn read_blobs(&self, path: &Path, objects: &[&str]) -> Result<Vec<Vec<u8>>> {
+        let repo = gix::discover(path)?;
+        objects
+            .iter()
+            .map(|object| {
+                let id = ObjectId::from_hex(object.as_bytes())?;
+                Ok(repo
+                    .find_object(id)
+                    .with_context(|| format!("Cannot read object {}", object))?
+                    .detach()
+                    .data)
+            })
+            .collect()
+    }
+}
+
+/// The repository containing `path`, and `path` relative to its working tree
+/// (`/`-separated, empty at the top).
+fn open(path: &Path) -> Result<(Repository, String)> {
+    let repo = gix::discover(path)?;
+    let workdir = repo
+        .workdir()
+        .context("The repository has no working tree")?;
+    let prefix = fs::canonicalize(path)?
+        .strip_prefix(fs::canonicalize(workdir)?)?
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    Ok((repo, prefix))
+}
+
+/// `path` (relative to the repository root) relative to `prefix`, if it is inside.
+fn relative<'a>(prefix: &str, path: &'a str) -> Option<&'a str> {
+    if prefix.is_empty() {
+        return Some(path);
+    }
+    match path.strip_prefix(prefix)? {
+        "" => Some(""),
+        rest => rest.strip_prefix('/'),
+    }
+}
+
+/// The commit `rev` names; an empty revision (as in `a..`) means HEAD.
+fn commit_id(repo: &Repository, rev: &str) -> Result<ObjectId> {
+    let rev = if rev.is_empty() { "HEAD" } else { rev };
+    Ok(repo
+        .rev_parse_single(rev)
+        .with_context(|| format!("Unknown revision {:?}", rev))?
+        .object()?
+        .peel_to_commit()?
+        .id)
+}
+
+/// Commits reachable from `rev` (or HEAD), newest first. `rev` may be a range: `a..b`
+/// leaves out what `a` reaches, `a...b` what both reach. With `since` the walk stops at
+/// commits older than that.
+fn walk<'r>(
+    repo: &'r Repository,
+    rev: Option<&str>,
+    since: Option<i64>,
+) -> Result<gix::revision::Walk<'r>> {
+    let rev = rev.unwrap_or("HEAD");
+    let (tips, hidden) = if let Some((a, b)) = rev.split_once("...") {
+        let (a, b) = (commit_id(repo, a)?, commit_id(repo, b)?);
+        (vec![a, b], vec![repo.merge_base(a, b)?.detach()])
+    } else if let Some((a, b)) = rev.split_once("..") {
+        (vec![commit_id(repo, b)?], vec![commit_id(repo, a)?])
+    } else {
+        (vec![commit_id(repo, rev)?], Vec::new())
+    };
+    let order = CommitTimeOrder::NewestFirst;
+    let sorting = match since {
+        Some(seconds) => Sorting::ByCommitTimeCutoff { order, seconds },
+        None => Sorting::ByCommitTime(order),
+    };
+    Ok(repo
+        .rev_walk(tips)
+        .with_hidden(hidden)
+        .sorting(sorting)
+        .all()?)
+}
+
+/// Files (relative to the repository root) a commit changed compared to its parent,
+/// following renames like `git log --name-only`. Merges list nothing.
+fn changed_files(repo: &Repository, id: ObjectId) -> Result<Vec<String>> {
+    let commit = repo.find_commit(id)?;
+    let parents: Vec<_> = commit.parent_ids().collect();
+    if parents.len() > 1 {
+        return Ok(Vec::new());
+    }
+    let new = commit.tree()?;
+    let old = match parents.first() {
+        Some(parent) => repo.find_commit(*parent)?.tree()?,
+        None => repo.empty_tree(),
+    };
+    let options = gix::diff::Options::default().with_rewrites(Some(Default::default()));
+    Ok(repo
+        .diff_tree_to_tree(&old, &new, options)?
+        .iter()
+        .filter(|change| !change.entry_mode().is_tree())
+        .map(|change| change.location().to_string())
+        .collect())
+}
+
+/// The diff of a commit against its first parent, restricted to `paths`.
+fn commit_patch(
+    repo: &Repository,
+    prefix: &str,
+    commit: &gix::Commit<'_>,
+    paths: &HashSet<String>,
+) -> Result<Option<String>> {
+    let keep = |side: Side| -> Side {
+        side.into_iter()
+            .filter(|(file, _)| paths.contains(file))
+            .collect()
+    };
+    let old = match commit.parent_ids().next() {
+        Some(parent) => keep(tree_side(repo, prefix, parent.detach())?),
+        None => Side::new(),
+    };
+    let new = keep(tree_side(repo, prefix, commit.id)?);
+    let mut patch = String::new();
+    diff_sides(repo, &old, &new, &mut patch)?;
+    Ok(patch_for(split_diff(&patch), paths))
+}
+
+/// The blob at `path` (relative to the repository root) in `commit`, if it is a file.
+fn blob_at(commit: &gix::Commit<'_>, path: &str) -> Result<Option<ObjectId>> {
+    Ok(commit
+        .tree()?
+        .lookup_entry_by_path(path)?
+        .filter(|entry| entry.mode().is_blob())
+        .map(|entry| entry.object_id()))
+}
+
+fn line_count(data: &[u8]) -> usize {
+    byte_lines_with_terminator(data).count()
+}
+
+/// For each line of `new`, the index of the line it was in `old`, or `None` if the diff
+/// from `old` added it.
+fn line_origins(old: &[u8], new: &[u8]) -> Vec<Option<usize>> {
+    let input = InternedInput::new(
+        byte_lines_with_terminator(old),
+        byte_lines_with_terminator(new),
+    );
+    let mut origins = Vec::with_capacity(input.after.len());
+    let mut old_line = 0;
+    gix::diff::blob::diff(
+        Algorithm::Myers,
+        &input,
+        |before: Range<u32>, after: Range<u32>| {
+            while origins.len() < after.start as usize {
+                origins.push(Some(old_line));
+                old_line += 1;
+            }
+            origins.extend(after.map(|_| None));
+            old_line = before.end as usize;
+        },
+    );
+    while origins.len() < input.after.len() {
+        origins.push(Some(old_line));
+        old_line += 1;
+    }
+    origins
+}
+
+fn tree_side(repo: &Repository, prefix: &str, commit: ObjectId) -> Result<Side> {
+    let tree = repo.find_commit(commit)?.tree()?;
+    let mut side = Side::new();
+    for entry in tree.traverse().breadthfirst.files()? {
+        if entry.mode.is_tree() || entry.mode.is_commit() {
+            continue;
+        }
+        let full = entry.filepath.to_string();
+        if let Some(file) = relative(prefix, &full) {
+            side.insert(
+                file.to_string(),
+                Version {
+                    id: entry.oid,
+                    mode: entry.mode.value() as u32,
+                    data: None,
+                },
+            );
+        }
+    }
+    Ok(side)
+}
+
+fn index_side(repo: &Repository, prefix: &str) -> Result<Side> {
+    let index = repo.index_or_empty()?;
+    let mut side = Side::new();
+    for entry in index.entries() {
+        let mode = entry.mode.bits();
+        // Conflicted entries have no single version to compare
+        if entry.stage_raw() != 0 || mode == SUBMODULE_MODE {
+            continue;
+        }
+        let full = entry.path(&index).to_string();
+        if let Some(file) = relative(prefix, &full) {
+            side.insert(
+                file.to_string(),
+                Version {
+                    id: entry.id,
+                    mode,
+                    data: None,
+                },
+            );
+        }
+    }
+    Ok(side)
+}
+
+/// The working-tree versions of the files in `index`; files deleted on disk are missing.
+fn worktree_side(repo: &Repository, prefix: &str, index: &Side) -> Result<Side> {
+    let root = workdir(repo, prefix)?;
+    // Files left out of a sparse checkout are unchanged, not deleted
+    let repo_index = repo.index_or_empty()?;
+    let skipped: BTreeSet<String> = repo_index
+        .entries()
+        .iter()
+        .filter(|e| e.flags.contains(gix::index::entry::Flags::SKIP_WORKTREE))
+        .filter_map(|e| Some(relative(prefix, &e.path(&repo_index).to_string())?.to_string()))
+        .collect();
+    let mut side = Side::new();
+    for (file, staged) in index {
+        if skipped.contains(file) {
+            side.insert(
+                file.clone(),
+                Version {
+                    id: staged.id,
+                    mode: staged.mode,
+                    data: None,
+                },
+            );
+        } else if let Some(version) = read_worktree(repo, &root.join(file))? {
+            side.insert(file.clone(), version);
+        }
+    }
+    Ok(side)
+}
+
+/// Files below `prefix` that are neither in `index` nor ignored.
+fn untracked(repo: &Repository, prefix: &str, index: &Side) -> Result<Side> {
+    let root = workdir(repo, prefix)?;
+    // Submodules and nested repositories belong to their own repositories
+    let repo_index = repo.index_or_empty()?;
+    let submodules: HashSet<std::path::PathBuf> = repo_index
+        .entries()
+        .iter()
+        .filter(|e| e.mode.bits() == SUBMODULE_MODE)
+        .filter_map(|e| Some(root.join(relative(prefix, &e.path(&repo_index).to_string())?)))
+        .collect();
+    let walker = ignore::WalkBuilder::new(&root)
+        .hidden(false)
+        .ignore(false)
+        .filter_entry(move |e| {
+            if e.file_name() == ".git" {
+                return false;
+            }
+            let nested = e.depth() > 0
+                && e.file_type().is_some_and(|t| t.is_dir())
+                && (submodules.contains(e.path()) || e.path().join(".git").exists());
+            !nested
+        })
+        .build();
+    let mut side = Side::new();
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_none_or(|t| t.is_dir()) {
+            continue;
+        }
+        let Ok(file) = entry.path().strip_prefix(&root) else {
+            continue;
+        };
+        let file = file
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        if index.contains_key(&file) {
+            continue;
+        }
+        if let Some(version) = read_worktree(repo, entry.path())? {
+            side.insert(file, version);
+        }
+    }
+    Ok(side)
+}
+
+fn workdir(repo: &Repository, prefix: &str) -> Result<std::path::PathBuf> {
+    let workdir = repo
+        .workdir()
+        .context("The repository has no working tree")?;
+    Ok(workdir.join(prefix))
+}
+
+fn read_worktree(repo: &Repository, path: &Path) -> Result<Option<Version>> {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+    let (data, mode) = if meta.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        (target.to_string_lossy().as_bytes().to_vec(), SYMLINK_MODE)
+    } else if meta.is_file() {
+        (fs::read(path)?, file_mode(&meta))
+    } else {
+        return Ok(None);
+    };
+    let id = gix::objs::compute_hash(repo.object_hash(), gix::objs::Kind::Blob, &data)?;
+    Ok(Some(Version {
+        id,
+        mode,
+        data: Some(data),
+    }))
+}
+
+/// Writes `git diff`-style patches for every path whose version differs. Files deleted
+/// on one side and added with identical contents on the other are reported as renames.
+fn diff_sides(repo: &Repository, old: &Side, new: &Side, out: &mut String) -> Result<()> {
+    let mut deleted: Vec<&String> = old.keys().filter(|p| !new.contains_key(*p)).collect();
+    let mut renamed_from: HashMap<&str, &str> = HashMap::new();
+    for (file, version) in new.iter().filter(|(p, _)| !old.contains_key(*p)) {
+        if let Some(pos) = deleted.iter().position(|d| old[*d].id == version.id) {
+            renamed_from.insert(file, deleted.remove(pos));
+        }
+    }
+    let renamed: BTreeSet<&str> = renamed_from.values().copied().collect();
+
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for file in paths {
+        if renamed.contains(file.as_str()) {
+            continue;
+        }
+        match (old.get(file), new.get(file)) {
+            (Some(a), Some(b)) if a.id == b.id && a.mode == b.mode => {}
+            (None, Some(b)) => match renamed_from.get(file.as_str()) {
+                Some(from) => write_patch(repo, out, Some(from), file, old.get(*from), Some(b))?,
+                None => write_patch(repo, out, None, file, None, Some(b))?,
+            },
+            (a, b) => write_patch(repo, out, None, file, a, b)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_patch(
+    repo: &Repository,
+    out: &mut String,
+    renamed_from: Option<&str>,
+    file: &str,
+    old: Option<&Version>,
+    new: Option<&Version>,
+) -> Result<()> {
+    let old_name = renamed_from.unwrap_or(file);
+    writeln!(out, "diff --git a/{} b/{}", old_name, file)?;
+    match (old, new) {
+        (None, Some(b)) => writeln!(out, "new file mode {:o}", b.mode)?,
+        (Some(a), None) => writeln!(out, "deleted file mode {:o}", a.mode)?,
+        (Some(a), Some(b)) if a.mode != b.mode => {
+            writeln!(out, "old mode {:o}\nnew mode {:o}", a.mode, b.mode)?
+        }
+        _ => {}
+    }
+    if renamed_from.is_some() {
+        writeln!(
+            out,
+            "similarity index 100%\nrename from {}\nrename to {}",
+            old_name, file
+        )?;
+    }
+    let null = ObjectId::null(repo.object_hash());
+    let old_id = old.map_or(null, |v| v.id);
+    let new_id = new.map_or(null, |v| v.id);
+    if old_id == new_id {
+        return Ok(());
+    }
+    write!(
+        out,
+        "index {}..{}",
+        old_id.to_hex_with_len(7),
+        new_id.to_hex_with_len(7)
+    )?;
+    match (old, new) {
+        (Some(a), Some(b)) if a.mode == b.mode => writeln!(out, " {:o}", a.mode)?,
+        _ => writeln!(out)?,
+    }
+
+    let old_data = contents(repo, old)?;
+    let new_data = contents(repo, new)?;
+    let old_label = old.map_or("/dev/null".to_string(), |_| format!("a/{}", old_name));
+    let new_label = new.map_or("/dev/null".to_string(), |_| format!("b/{}", file));
+    if is_binary(&old_data) || is_binary(&new_data) {
+        writeln!(out, "Binary files {} and {} differ", old_label, new_label)?;
+        return Ok(());
+    }
+    writeln!(out, "--- {}\n+++ {}", old_label, new_label)?;
+    let input = InternedInput::new(
+        byte_lines_with_terminator(&old_data),
+        byte_lines_with_terminator(&new_data),
+    );
+    let hunks = gix::diff::blob::diff(
+        Algorithm::Myers,
+        &input,
+        UnifiedDiff::new(&input, Hunks(String::new()), ContextSize::symmetrical(3)),
+    )?;
+    out.push_str(&hunks);
+    Ok(())
+}
+
+fn contents(repo: &Repository, version: Option<&Version>) -> Result<Vec<u8>> {
+    Ok(match version {
+        None => Vec::new(),
+        Some(Version {
+            data: Some(data), ..
+        }) => data.clone(),
+        Some(version) => repo.find_object(version.id)?.detach().data,
+    })
+}
+
+/// Renders hunks with git's headers and end-of-file markers.
+struct Hunks(String);
+
+impl ConsumeHunk for Hunks {
+    type Out = String;
+
+    fn consume_hunk(
+        &mut self,
+        header: HunkHeader,
+        lines: &[(DiffLineKind, &[u8])],
+    ) -> std::io::Result<()> {
+        let out = &mut self.0;
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(header.before_hunk_start, header.before_hunk_len),
+            hunk_range(header.after_hunk_start, header.after_hunk_len)
+        ));
+        for (kind, line) in lines {
+            out.push(match kind {
+                DiffLineKind::Context => ' ',
+                DiffLineKind::Add => '+',
+                DiffLineKind::Remove => '-',
+            });
+            out.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> String {
+        self.0
+    }
+}
+
+fn hunk_range(start: u32, len: u32) -> String {
+    match len {
+        0 => format!("{},0", start - 1),
+        1 => start.to_string(),
+        _ => format!("{},{}", start, len),
+    }
+}
+
+/// The directory of a local repository URL (a path or `file://` URL).
+fn local_source(url: &str) -> Result<&Path> {
+    let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+    if !path.is_dir() {
+        anyhow::bail!(
+            "Only local repositories can be cloned natively, not {}",
+            url
+        );
+    }
+    Ok(path)
+}
+
+/// Writes the files of `tree` below `target` (only those below `sparse`, when given).
+fn checkout(repo: &Repository, tree: ObjectId, target: &Path, sparse: Option<&str>) -> Result<()> {
+    for entry in repo.find_tree(tree)?.traverse().breadthfirst.files()? {
+        let file = entry.filepath.to_string();
+        if sparse.is_some_and(|dir| relative(dir, &file).is_none()) {
+            continue;
+        }
+        let path = target.join(file);
+        if entry.mode.is_tree() || entry.mode.is_commit() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let data = repo.find_object(entry.oid)?.detach().data;
+        if entry.mode.is_link() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(String::from_utf8_lossy(&data).as_ref(), &path)?;
+            #[cfg(not(unix))]
+            fs::write(&path, &data)?;
+            continue;
+        }
+        fs::write(&path, &data)?;
+        #[cfg(unix)]
+        if entry.mode.is_executable() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(())
+}
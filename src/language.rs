@@ -1,7 +1,73 @@
 use regex::Regex;
-use tree_sitter::{Parser, Query, QueryCursor};
+use tree_sitter::{Language, Parser, Query, QueryCursor, Tree};
 use streaming_iterator::StreamingIterator;
 
+/// Maps a file path to a conventional language identifier (as used by markdown fences
+/// and syntax highlighters), based on its extension or well-known file name.
+pub fn detect_language(path: &str) -> Option<&'static str> {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    match file_name {
+        "Dockerfile" => return Some("dockerfile"),
+        "Makefile" | "makefile" | "GNUmakefile" => return Some("makefile"),
+        "CMakeLists.txt" => return Some("cmake"),
+        _ => {}
+    }
+
+    let extension = file_name.rsplit_once('.').map(|(_, e)| e)?;
+    let lang = match extension.to_ascii_lowercase().as_str() {
+        "rs" => "rust",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "py" | "pyi" => "python",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "scala" => "scala",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "cxx" | "hpp" | "hh" | "hxx" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "dart" => "dart",
+        "lua" => "lua",
+        "pl" | "pm" => "perl",
+        "r" => "r",
+        "ex" | "exs" => "elixir",
+        "erl" => "erlang",
+        "hs" => "haskell",
+        "ml" | "mli" => "ocaml",
+        "clj" | "cljs" => "clojure",
+        "zig" => "zig",
+        "sh" | "bash" => "bash",
+        "zsh" => "zsh",
+        "fish" => "fish",
+        "ps1" => "powershell",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "sass" => "sass",
+        "less" => "less",
+        "vue" => "vue",
+        "svelte" => "svelte",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "xml" => "xml",
+        "md" | "markdown" => "markdown",
+        "proto" => "protobuf",
+        "graphql" | "gql" => "graphql",
+        "tf" => "hcl",
+        "ini" | "cfg" => "ini",
+        "diff" | "patch" => "diff",
+        _ => return None,
+    };
+    Some(lang)
+}
+
 pub mod comments {
     use super::*;
 
@@ -29,10 +95,9 @@ pub mod comments {
 pub mod compression {
     use super::*;
 
-    pub fn compress_content(content: &str, extension: &str) -> Option<String> {
-        let mut parser = Parser::new();
-
-        let (language, query_str) = match extension {
+    /// Tree-sitter grammar and definition query for a file extension.
+    pub(super) fn grammar(extension: &str) -> Option<(Language, &'static str)> {
+        let grammar = match extension {
             "rs" => (tree_sitter_rust::LANGUAGE.into(), RUST_QUERY),
             "ts" | "tsx" => (tree_sitter_typescript::LANGUAGE_TSX.into(), TS_QUERY),
             "js" | "jsx" => (tree_sitter_javascript::LANGUAGE.into(), JS_QUERY),
@@ -40,9 +105,29 @@ pub mod compression {
             "go" => (tree_sitter_go::LANGUAGE.into(), GO_QUERY),
             _ => return None, // Language not supported for compression
         };
+        Some(grammar)
+    }
 
+    /// Parses `content` with the grammar for `extension`, if there is one.
+    pub fn parse(content: &str, extension: &str) -> Option<Tree> {
+        let (language, _) = grammar(extension)?;
+        let mut parser = Parser::new();
         parser.set_language(&language).ok()?;
-        let tree = parser.parse(content, None)?;
+        parser.parse(content, None)
+    }
+
+    /// Keeps the definitions' signatures and headers. `tree` is the parse of `content`
+    /// if the caller already has one.
+    pub fn compress_content(content: &str, extension: &str, tree: Option<&Tree>) -> Option<String> {
+        let (language, query_str) = grammar(extension)?;
+        let parsed;
+        let tree = match tree {
+            Some(tree) => tree,
+            None => {
+                parsed = parse(content, extension)?;
+                &parsed
+            }
+        };
         let query = Query::new(&language, query_str).ok()?;
         let mut cursor = QueryCursor::new();
 
@@ -62,7 +147,7 @@ pub mod compression {
         }
 
         // Sort and merge overlapping ranges
-        ranges.sort_by(|a, b| a.start.cmp(&b.start));
+        ranges.sort_by_key(|r| r.start);
 
         let mut merged_ranges = Vec::new();
         let mut current_range = ranges[0].clone();
@@ -131,3 +216,712 @@ pub mod compression {
         (type_declaration) @t
     "#;
 }
+
+pub mod chunking {
+    use super::*;
+    use crate::fs_tools;
+    use std::ops::Range;
+
+    /// A semantically meaningful slice of a file (a definition, a markdown section, or a
+    /// run of lines), small enough to embed on its own.
+    #[derive(Debug, Clone)]
+    pub struct Chunk {
+        pub symbol: Option<String>,
+        pub kind: String,
+        pub byte_range: Range<usize>,
+        pub start_line: usize,
+        pub end_line: usize,
+        pub token_count: usize,
+    }
+
+    /// Splits `content` into chunks of at most `max_tokens` tokens. Definitions found by
+    /// the tree-sitter compression queries become one chunk each; code between them, and
+    /// files in unsupported languages, are chunked by lines, and a line longer than the
+    /// limit by tokens. Markdown is chunked by heading.
+    ///
+    /// Ranges are offsets into `content`, the packed text, which differs from the file on
+    /// disk once it is compressed or stripped of comments or empty lines.
+    pub fn chunk_content(content: &str, extension: &str, max_tokens: usize) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        let mut emit = |range: Range<usize>, kind: &str, symbol: Option<String>| {
+            push_chunks(&mut chunks, content, range, kind, symbol, max_tokens);
+        };
+
+        if let Some(definitions) = definitions(content, extension) {
+            let mut pos = 0;
+            for def in definitions {
+                emit(pos..def.range.start, "code", None);
+                pos = def.range.end;
+                emit(def.range, &def.kind, def.symbol);
+            }
+            emit(pos..content.len(), "code", None);
+        } else if matches!(extension, "md" | "markdown") {
+            for (range, heading) in markdown_sections(content) {
+                emit(range, "section", heading);
+            }
+        } else {
+            emit(0..content.len(), "file", None);
+        }
+
+        chunks
+    }
+
+    struct Definition {
+        range: Range<usize>,
+        kind: String,
+        symbol: Option<String>,
+    }
+
+    /// Names of the innermost definitions (functions, types, ...) containing any of the
+    /// given 1-based `lines`, in source order. Lines outside every definition are skipped.
+    pub fn enclosing_definitions(content: &str, extension: &str, lines: &[usize]) -> Vec<String> {
+        let Some(nodes) = all_definitions(content, extension) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = Vec::new();
+        for &line in lines {
+            let Some(offset) = line_offset(content, line) else {
+                continue;
+            };
+            // Sorted by start, outer before inner: the last match is the innermost
+            let innermost = nodes
+                .iter()
+                .rfind(|d| d.range.start <= offset && offset < d.range.end);
+            if let Some(def) = innermost {
+                let name = def.symbol.clone().unwrap_or_else(|| def.kind.clone());
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// Byte offset of the first non-blank character of a 1-based line.
+    fn line_offset(content: &str, line: usize) -> Option<usize> {
+        let start = if line <= 1 {
+            0
+        } else {
+            content.match_indices('\n').nth(line - 2)?.0 + 1
+        };
+        let rest = &content[start..];
+        let indent = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        Some(start + indent)
+    }
+
+    /// Outermost definitions captured by the compression query, in source order.
+    fn definitions(content: &str, extension: &str) -> Option<Vec<Definition>> {
+        let nodes = all_definitions(content, extension)?;
+        // Keep only top-level definitions; nested ones (e.g. methods) stay inside their parent
+        let mut outermost: Vec<Definition> = Vec::new();
+        for node in nodes {
+            if outermost.last().is_none_or(|last| node.range.start >= last.range.end) {
+                outermost.push(node);
+            }
+        }
+        Some(outermost)
+    }
+
+    /// Every definition captured by the compression query, sorted by start with enclosing
+    /// definitions before the ones nested in them.
+    fn all_definitions(content: &str, extension: &str) -> Option<Vec<Definition>> {
+        let (language, query_str) = compression::grammar(extension)?;
+        let mut parser = Parser::new();
+        parser.set_language(&language).ok()?;
+        let tree = parser.parse(content, None)?;
+        let query = Query::new(&language, query_str).ok()?;
+        let mut cursor = QueryCursor::new();
+
+        let mut nodes = Vec::new();
+        let mut matches = cursor.matches(&query, tree.root_node(), content.as_bytes());
+        while let Some(m) = matches.next() {
+            for capture in m.captures {
+                let node = capture.node;
+                let symbol = node
+                    .child_by_field_name("name")
+                    .or_else(|| node.child_by_field_name("type"))
+                    .and_then(|n| n.utf8_text(content.as_bytes()).ok())
+                    .map(|s| s.to_string());
+                nodes.push(Definition {
+                    range: node.byte_range(),
+                    kind: node.kind().to_string(),
+                    symbol,
+                });
+            }
+        }
+
+        nodes.sort_by(|a, b| {
+            a.range.start
+                .cmp(&b.range.start)
+                .then(b.range.end.cmp(&a.range.end))
+        });
+        Some(nodes)
+    }
+
+    /// Splits markdown at ATX headings (ignoring `#` lines inside code fences).
+    fn markdown_sections(content: &str) -> Vec<(Range<usize>, Option<String>)> {
+        let mut sections = Vec::new();
+        let mut start = 0;
+        let mut heading = None;
+        let mut in_fence = false;
+        let mut offset = 0;
+
+        for line in content.split_inclusive('\n') {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+            } else if !in_fence && trimmed.starts_with('#') {
+                if offset > start {
+                    sections.push((start..offset, heading.take()));
+                    start = offset;
+                }
+                heading = Some(trimmed.trim_start_matches('#').trim().to_string());
+            }
+            offset += line.len();
+        }
+        sections.push((start..content.len(), heading));
+        sections
+    }
+
+    fn push_chunks(
+        chunks: &mut Vec<Chunk>,
+        content: &str,
+        range: Range<usize>,
+        kind: &str,
+        symbol: Option<String>,
+        max_tokens: usize,
+    ) {
+        let text = &content[range.clone()];
+        let start = range.start + (text.len() - text.trim_start().len());
+        let end = range.start + text.trim_end().len();
+        if start >= end {
+            return;
+        }
+
+        let make = |r: Range<usize>| Chunk {
+            symbol: symbol.clone(),
+            kind: kind.to_string(),
+            start_line: line_at(content, r.start),
+            end_line: line_at(content, r.end - 1),
+            token_count: fs_tools::count_tokens(&content[r.clone()]),
+            byte_range: r,
+        };
+
+        let tokens = fs_tools::count_tokens(&content[start..end]);
+        if tokens <= max_tokens {
+            chunks.push(make(start..end));
+            return;
+        }
+
+        // Too large: fall back to runs of whole lines
+        let mut piece_start = start;
+        let mut piece_tokens = 0;
+        let mut offset = start;
+        for line in content[start..end].split_inclusive('\n') {
+            let line_tokens = fs_tools::count_tokens(line);
+            if piece_tokens > 0 && piece_tokens + line_tokens > max_tokens {
+                chunks.push(make(piece_start..offset));
+                piece_start = offset;
+                piece_tokens = 0;
+            }
+            if line_tokens > max_tokens {
+                // A line too long on its own (minified code, data): cut it by tokens
+                let mut cut_start = offset;
+                for cut in fs_tools::token_cuts(line, max_tokens) {
+                    chunks.push(make(cut_start..offset + cut));
+                    cut_start = offset + cut;
+                }
+                piece_start = cut_start;
+            } else {
+                piece_tokens += line_tokens;
+            }
+            offset += line.len();
+        }
+        if piece_start < end {
+            chunks.push(make(piece_start..end));
+        }
+    }
+
+    /// 1-based line number of the byte at `offset`.
+    fn line_at(content: &str, offset: usize) -> usize {
+        content.as_bytes()[..offset].iter().filter(|&&b| b == b'\n').count() + 1
+    }
+}
+
+pub mod highlight {
+    use super::*;
+
+    /// The tree-sitter grammar for a language name as `detect_language` reports it.
+    fn grammar(language: &str) -> Option<Language> {
+        let extension = match language {
+            "rust" => "rs",
+            "typescript" => "ts",
+            "tsx" => "tsx",
+            "javascript" | "jsx" => "js",
+            "python" => "py",
+            "go" => "go",
+            _ => return None,
+        };
+        compression::grammar(extension).map(|(language, _)| language)
+    }
+
+    /// The class of a syntax node highlighted as a whole: `c` for comments, `s` for
+    /// strings, `n` for numbers and `k` for keywords and literal constants.
+    fn class(node: tree_sitter::Node) -> Option<&'static str> {
+        let kind = node.kind();
+        if !node.is_named() {
+            // Anonymous leaves spelled as words are the grammar's keywords
+            let is_word = kind.chars().all(|c| c.is_ascii_alphabetic() || c == '_');
+            return (is_word && node.child_count() == 0).then_some("k");
+        }
+        if kind.contains("comment") {
+            Some("c")
+        } else if kind.contains("string") || matches!(kind, "char_literal" | "rune_literal") {
+            Some("s")
+        } else if matches!(
+            kind,
+            "integer_literal" | "float_literal" | "int_literal" | "imaginary_literal" | "number"
+                | "integer" | "float"
+        ) {
+            Some("n")
+        } else if matches!(
+            kind,
+            "true" | "false" | "none" | "null" | "undefined" | "nil" | "self" | "this"
+        ) {
+            Some("k")
+        } else {
+            None
+        }
+    }
+
+    /// Escapes `code` for HTML and wraps comments, strings, numbers and keywords, as the
+    /// language's tree-sitter grammar parses them, in `<span class="c|s|n|k">`
+    /// (`add|del|hunk` lines for diffs). Languages without a grammar are only escaped.
+    pub fn highlight_html(code: &str, language: Option<&str>) -> String {
+        if language == Some("diff") {
+            return highlight_diff(code);
+        }
+        let Some(grammar) = language.and_then(grammar) else {
+            return escape_html(code);
+        };
+        let mut parser = Parser::new();
+        if parser.set_language(&grammar).is_err() {
+            return escape_html(code);
+        }
+        let Some(tree) = parser.parse(code, None) else {
+            return escape_html(code);
+        };
+
+        let mut out = String::with_capacity(code.len() + code.len() / 4);
+        // Everything before `pos` is written; text between highlighted nodes is escaped
+        let mut pos = 0;
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            match class(node) {
+                Some(class) if node.start_byte() >= pos && node.end_byte() > node.start_byte() => {
+                    out.push_str(&escape_html(&code[pos..node.start_byte()]));
+                    push_span(&mut out, class, &code[node.byte_range()]);
+                    pos = node.end_byte();
+                }
+                _ => {
+                    if cursor.goto_first_child() {
+                        continue;
+                    }
+                }
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+        out.push_str(&escape_html(&code[pos..]));
+        out
+    }
+
+    /// Diffs are highlighted per line: additions, deletions and hunk headers.
+    fn highlight_diff(code: &str) -> String {
+        let mut out = String::with_capacity(code.len() + code.len() / 4);
+        for line in code.split_inclusive('\n') {
+            let class = if line.starts_with("+++") || line.starts_with("---") {
+                None
+            } else if line.starts_with('+') {
+                Some("add")
+            } else if line.starts_with('-') {
+                Some("del")
+            } else if line.starts_with("@@") {
+                Some("hunk")
+            } else {
+                None
+            };
+            match class {
+                Some(class) => push_span(&mut out, class, line),
+                None => out.push_str(&escape_html(line)),
+            }
+        }
+        out
+    }
+
+    fn push_span(out: &mut String, class: &str, text: &str) {
+        out.push_str("<span class=\"");
+        out.push_str(class);
+        out.push_str("\">");
+        out.push_str(&escape_html(text));
+        out.push_str("</span>");
+    }
+
+    pub fn escape_html(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+}
+
+pub mod imports {
+    use super::*;
+    use tree_sitter::Node;
+
+    /// Something a source file imports, relative to the pack root.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Import {
+        /// A file at exactly this path.
+        File(String),
+        /// A file at this path or ending in `/<path>` (a Python module whose package root
+        /// is unknown).
+        Module(String),
+        /// The files directly inside a directory whose path is a suffix of this import
+        /// path (a Go package).
+        Package(String),
+    }
+
+    /// Files and packages imported by the file at `path` (relative to the pack root).
+    /// Handles Rust `mod`/`use crate|self|super`, Python, JavaScript/TypeScript relative
+    /// imports and Go imports; an import of an external package yields candidates that
+    /// match no packed file. `tree` is the file's parse if the caller already has one.
+    pub fn local_imports(path: &str, content: &str, tree: Option<&Tree>) -> Vec<Import> {
+        let (dir, file_name) = path.rsplit_once('/').unwrap_or(("", path));
+        let extension = file_name.rsplit_once('.').map(|(_, e)| e).unwrap_or("");
+        // Variants share the grammar of their main extension
+        let grammar_extension = match extension {
+            "pyi" => "py",
+            "mts" | "cts" => "ts",
+            "mjs" | "cjs" => "js",
+            other => other,
+        };
+        let parsed;
+        let tree = match tree {
+            Some(tree) => tree,
+            None => match compression::parse(content, grammar_extension) {
+                Some(tree) => {
+                    parsed = tree;
+                    &parsed
+                }
+                None => return Vec::new(),
+            },
+        };
+        let source = content.as_bytes();
+        let mut imports = Vec::new();
+        match grammar_extension {
+            "rs" => {
+                let resolver = RustModules::new(dir, file_name);
+                for_each_node(tree, |node| resolver.push(node, source, &mut imports));
+            }
+            "py" => for_each_node(tree, |node| python_import(node, source, dir, &mut imports)),
+            "ts" | "tsx" | "js" | "jsx" => {
+                for_each_node(tree, |node| script_import(node, source, dir, &mut imports))
+            }
+            "go" => for_each_node(tree, |node| {
+                if node.kind() == "import_spec" {
+                    if let Some(path) = node.child_by_field_name("path") {
+                        imports.push(Import::Package(unquote(text(path, source)).to_string()));
+                    }
+                }
+            }),
+            _ => {}
+        }
+        imports
+    }
+
+    /// Calls `f` on every node of `tree`, in document order.
+    fn for_each_node<'t>(tree: &'t Tree, mut f: impl FnMut(Node<'t>)) {
+        let mut cursor = tree.walk();
+        loop {
+            f(cursor.node());
+            if cursor.goto_first_child() || cursor.goto_next_sibling() {
+                continue;
+            }
+            loop {
+                if !cursor.goto_parent() {
+                    return;
+                }
+                if cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn text<'s>(node: Node, source: &'s [u8]) -> &'s str {
+        node.utf8_text(source).unwrap_or_default()
+    }
+
+    /// A string literal without its quotes.
+    fn unquote(literal: &str) -> &str {
+        literal.trim_matches(|c| matches!(c, '"' | '\'' | '`'))
+    }
+
+    /// Where the modules a Rust file refers to live.
+    struct RustModules {
+        /// Where this module's submodules live.
+        module_dir: String,
+        /// Where its siblings (`super::`) live.
+        parent_dir: String,
+        crate_root: String,
+    }
+
+    impl RustModules {
+        fn new(dir: &str, file_name: &str) -> Self {
+            let is_root = matches!(file_name, "main.rs" | "lib.rs" | "mod.rs");
+            let module_dir = if is_root {
+                dir.to_string()
+            } else {
+                join(dir, file_name.trim_end_matches(".rs"))
+            };
+            let parent_dir = if is_root {
+                parent(dir)
+            } else {
+                dir.to_string()
+            };
+            // The crate root is the innermost `src` directory, if any
+            let crate_root = match dir.rsplit_once("/src") {
+                Some((prefix, rest)) if rest.is_empty() || rest.starts_with('/') => {
+                    format!("{}/src", prefix)
+                }
+                _ if dir == "src" || dir.starts_with("src/") => "src".to_string(),
+                _ => dir.to_string(),
+            };
+            Self {
+                module_dir,
+                parent_dir,
+                crate_root,
+            }
+        }
+
+        fn push(&self, node: Node, source: &[u8], imports: &mut Vec<Import>) {
+            match node.kind() {
+                // `mod x;`, not an inline `mod x { ... }`
+                "mod_item" if node.child_by_field_name("body").is_none() => {
+                    if let Some(name) = node.child_by_field_name("name") {
+                        push_rust_module(imports, &self.module_dir, &[text(name, source)]);
+                    }
+                }
+                "use_declaration" => {
+                    let mut paths = Vec::new();
+                    if let Some(argument) = node.child_by_field_name("argument") {
+                        use_paths(argument, source, &[], &mut paths);
+                    }
+                    for path in paths {
+                        self.push_use(&path, imports);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        /// Modules along one `use` path starting with `crate`, `self` or `super`.
+        fn push_use(&self, path: &[&str], imports: &mut Vec<Import>) {
+            let Some((&first, rest)) = path.split_first() else {
+                return;
+            };
+            let mut base = match first {
+                "crate" => self.crate_root.clone(),
+                "self" => self.module_dir.clone(),
+                "super" => self.parent_dir.clone(),
+                _ => return,
+            };
+            let mut segments = rest;
+            while let Some((&"super", rest)) = segments.split_first() {
+                base = parent(&base);
+                segments = rest;
+            }
+            // Items of the parent module live in its own file
+            if first == "super" {
+                imports.push(Import::File(format!("{}.rs", base)));
+                imports.push(Import::File(format!("{}/mod.rs", base)));
+            }
+            push_rust_module(imports, &base, segments);
+        }
+    }
+
+    /// Every full path a `use` tree names, with `{a, b}` lists expanded and `self`, `*`
+    /// and `as` aliases dropped.
+    fn use_paths<'s>(
+        node: Node,
+        source: &'s [u8],
+        prefix: &[&'s str],
+        out: &mut Vec<Vec<&'s str>>,
+    ) {
+        let path_of = |node: Option<Node>| {
+            let mut path = prefix.to_vec();
+            if let Some(node) = node {
+                path.extend(segments(node, source));
+            }
+            path
+        };
+        match node.kind() {
+            "use_list" => {
+                let mut cursor = node.walk();
+                for item in node.named_children(&mut cursor) {
+                    use_paths(item, source, prefix, out);
+                }
+            }
+            "scoped_use_list" => {
+                let path = path_of(node.child_by_field_name("path"));
+                if let Some(list) = node.child_by_field_name("list") {
+                    use_paths(list, source, &path, out);
+                }
+            }
+            "use_as_clause" => out.push(path_of(node.child_by_field_name("path"))),
+            "use_wildcard" => out.push(path_of(node.named_child(0))),
+            // `a::{self}` names `a` itself
+            "self" if !prefix.is_empty() => out.push(prefix.to_vec()),
+            _ => out.push(path_of(Some(node))),
+        }
+    }
+
+    /// The segments of a path like `super::a::b`.
+    fn segments<'s>(node: Node, source: &'s [u8]) -> Vec<&'s str> {
+        if node.kind() == "scoped_identifier" {
+            let mut path = node
+                .child_by_field_name("path")
+                .map(|p| segments(p, source))
+                .unwrap_or_default();
+            path.extend(node.child_by_field_name("name").map(|n| text(n, source)));
+            path
+        } else {
+            vec![text(node, source)]
+        }
+    }
+
+    /// Every module along `segments`: `a.rs`, `a/mod.rs`, `a/b.rs`, `a/b/mod.rs`, ...
+    fn push_rust_module(imports: &mut Vec<Import>, base: &str, segments: &[&str]) {
+        for depth in 1..=segments.len() {
+            let module = join(base, &segments[..depth].join("/"));
+            imports.push(Import::File(format!("{}.rs", module)));
+            imports.push(Import::File(format!("{}/mod.rs", module)));
+        }
+    }
+
+    fn python_import(node: Node, source: &[u8], dir: &str, imports: &mut Vec<Import>) {
+        // What an `x` or `x as y` clause imports
+        let imported = |name: Node| {
+            let name = match name.kind() {
+                "aliased_import" => name.child_by_field_name("name").unwrap_or(name),
+                _ => name,
+            };
+            text(name, source).split('.').collect::<Vec<_>>()
+        };
+        let mut cursor = node.walk();
+        match node.kind() {
+            "import_statement" => {
+                for name in node.children_by_field_name("name", &mut cursor) {
+                    let module = imported(name).join("/");
+                    imports.push(Import::Module(format!("{}.py", module)));
+                    imports.push(Import::Module(format!("{}/__init__.py", module)));
+                }
+            }
+            "import_from_statement" => {
+                let Some(module_name) = node.child_by_field_name("module_name") else {
+                    return;
+                };
+                let (dots, module) = if module_name.kind() == "relative_import" {
+                    let mut inner = module_name.walk();
+                    let children: Vec<Node> = module_name.named_children(&mut inner).collect();
+                    let dots = children
+                        .iter()
+                        .find(|c| c.kind() == "import_prefix")
+                        .map_or(0, |p| text(*p, source).len());
+                    let module = children.iter().find(|c| c.kind() == "dotted_name");
+                    (dots, module.map(|m| text(*m, source)).unwrap_or_default())
+                } else {
+                    (0, text(module_name, source))
+                };
+                let module: Vec<&str> = module.split('.').filter(|s| !s.is_empty()).collect();
+                // `from . import x` may import the module `x`; so may `from a import x`
+                let names = node
+                    .children_by_field_name("name", &mut cursor)
+                    .map(|n| [module.as_slice(), &imported(n)].concat());
+                let base = (dots > 0).then(|| (1..dots).fold(dir.to_string(), |d, _| parent(&d)));
+                for segments in std::iter::once(module.clone()).chain(names) {
+                    if segments.is_empty() {
+                        continue;
+                    }
+                    let module = segments.join("/");
+                    for file in [format!("{}.py", module), format!("{}/__init__.py", module)] {
+                        imports.push(match &base {
+                            Some(base) => Import::File(join(base, &file)),
+                            None => Import::Module(file),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    const SCRIPT_EXTENSIONS: &[&str] = &["ts", "tsx", "mts", "cts", "js", "jsx", "mjs", "cjs"];
+
+    fn script_import(node: Node, source: &[u8], dir: &str, imports: &mut Vec<Import>) {
+        // `import ... from "x"`, `export ... from "x"`, `import("x")` and `require("x")`
+        let specifier = match node.kind() {
+            "import_statement" | "export_statement" => node.child_by_field_name("source"),
+            "call_expression" => {
+                let callee = node.child_by_field_name("function");
+                let is_import =
+                    callee.is_some_and(|f| f.kind() == "import" || text(f, source) == "require");
+                node.child_by_field_name("arguments")
+                    .and_then(|args| args.named_child(0))
+                    .filter(|arg| is_import && arg.kind() == "string")
+            }
+            _ => None,
+        };
+        let Some(specifier) = specifier.map(|s| unquote(text(s, source))) else {
+            return;
+        };
+        if !specifier.starts_with('.') {
+            return;
+        }
+        let target = join(dir, specifier);
+        // TypeScript imports its own files by their compiled `.js` names
+        let stem = target
+            .strip_suffix(".js")
+            .or_else(|| target.strip_suffix(".mjs"))
+            .unwrap_or(&target);
+        imports.push(Import::File(target.clone()));
+        for ext in SCRIPT_EXTENSIONS {
+            imports.push(Import::File(format!("{}.{}", stem, ext)));
+            imports.push(Import::File(join(&target, &format!("index.{}", ext))));
+        }
+    }
+
+    /// Joins a relative path onto a directory, resolving `.` and `..`.
+    fn join(dir: &str, rel: &str) -> String {
+        let mut parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
+        for part in rel.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop();
+                }
+                _ => parts.push(part),
+            }
+        }
+        parts.join("/")
+    }
+
+    fn parent(dir: &str) -> String {
+        dir.rsplit_once('/').map_or("", |(p, _)| p).to_string()
+    }
+}
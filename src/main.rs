@@ -1,28 +1,64 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
 use globset::{Glob, GlobSetBuilder};
 use ignore::WalkBuilder;
 use indicatif::{ProgressBar, ProgressStyle};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+mod cache;
 mod cli;
 mod config;
 mod fs_tools;
 mod git;
 mod language;
+mod ordering;
 mod output;
+mod prompts;
+mod remote;
+mod review;
 mod security;
-
-use cli::{Cli, OutputStyle};
+mod snapshot;
+mod split;
+mod state;
+mod survey;
+mod template;
+mod tree;
+
+use cache::RemoteCache;
+use cli::{CacheCommand, Cli, Command, OutputStyle, SortOrder};
 use config::RustymixConfig;
-use output::ProcessedFile;
+use git::FileDiff;
+use output::{PackContext, ProcessedFile};
+use prompts::{PromptPhase, PromptVars};
+use state::PackManifest;
+use tree::DirectoryTree;
+
+/// `println!` for progress and summary messages, redirected to stderr when the pack is
+/// being written to stdout.
+macro_rules! status {
+    ($to_stdout:expr, $($arg:tt)*) => {
+        if $to_stdout {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let cli = Cli::parse_args();
+    // With `-o -` the pack itself goes to stdout, so progress messages move to stderr
+    let to_stdout = cli.output.as_deref() == Some("-");
+    let review_args = match &cli.command {
+        Some(Command::Review { range, directory }) => Some((range.clone(), directory.clone())),
+        _ => None,
+    };
 
     // 1. Setup Config
     let mut config = RustymixConfig::default();
@@ -36,7 +72,7 @@ async fn main() -> Result<()> {
         match serde_json::from_str::<RustymixConfig>(&content) {
             Ok(file_config) => {
                 if cli.verbose {
-                    println!("Loaded config from {}", config_path);
+                    status!(to_stdout, "Loaded config from {}", config_path);
                 }
                 config = file_config;
             }
@@ -47,15 +83,48 @@ async fn main() -> Result<()> {
             }
         }
     } else if cli.verbose {
-        println!("Config file {} not found", config_path);
+        status!(to_stdout, "Config file {} not found", config_path);
+    }
+
+    if let Some(Command::Cache {
+        action: CacheCommand::Prune { max_size, all },
+    }) = &cli.command
+    {
+        let cache = RemoteCache::new(&config.cache, cli.cache_dir.as_deref())?;
+        let limit = match max_size {
+            _ if *all => 0,
+            Some(size) => cache::parse_size(size)?,
+            None => cache.max_size(),
+        };
+        let report = cache.prune(limit, None)?;
+        println!(
+            "Removed {} cached clone(s), freeing {}; {} left in {}",
+            report.removed,
+            cache::format_size(report.freed),
+            cache::format_size(report.remaining),
+            cache.dir().display()
+        );
+        return Ok(());
     }
 
     // --- ARGUMENT PARSING & OVERRIDES ---
     if let Some(s) = &cli.output {
         config.output.file_path = s.clone();
     }
-    if cli.style != OutputStyle::Xml {
-        config.output.style = cli.style;
+    if let Some(styles) = &cli.style {
+        config.output.styles = styles.clone();
+    }
+    if let Some(t) = &cli.template {
+        config.output.template_path = Some(t.clone());
+        // Alongside explicitly listed styles, otherwise instead of the configured ones
+        if cli.style.is_none() {
+            config.output.styles = vec![OutputStyle::Template];
+        } else if !config.output.styles.contains(&OutputStyle::Template) {
+            config.output.styles.push(OutputStyle::Template);
+        }
+    }
+    if let Some(n) = cli.chunk_max_tokens {
+        config.output.chunk_max_tokens = n;
     }
     if cli.copy {
         config.output.copy_to_clipboard = true;
@@ -78,12 +147,76 @@ async fn main() -> Result<()> {
     if cli.include_empty_directories {
         config.output.include_empty_directories = true;
     }
-    if cli.include_diffs {
+    if cli.xml_cdata {
+        config.output.xml_cdata = true;
+    }
+    if let Some(fields) = cli.file_metadata {
+        config.output.file_metadata = fields;
+    }
+    if cli.tree_metrics {
+        config.output.directory_tree_metrics = true;
+    }
+    // A review pack: the change set in full with its diff, everything else as skeleton
+    if review_args.is_some() {
+        if cli.remote.is_some() {
+            anyhow::bail!(
+                "`review` works on a local repository and cannot be combined with --remote"
+            );
+        }
+        if cli.git_ref.is_some() {
+            anyhow::bail!("`review` already packs the head of its range; drop --ref");
+        }
+        config.output.compress = true;
+    }
+    let git: Arc<dyn git::GitBackend> = git::backend(cli.git_backend).into();
+    let mut diff_options = git::DiffOptions {
+        base: cli.diff_base.clone(),
+        range: cli.diff_range.clone(),
+        staged: cli.diff_staged,
+        unstaged: cli.diff_unstaged,
+    };
+    // An explicitly requested comparison should fail loudly rather than be skipped
+    let explicit_diff = diff_options.base.is_some()
+        || diff_options.range.is_some()
+        || diff_options.staged
+        || diff_options.unstaged
+        || review_args.is_some();
+    if cli.include_diffs || explicit_diff {
         config.output.include_diffs = true;
     }
-    if cli.include_logs {
+    // The working tree is not part of a pack of another revision
+    if cli.git_ref.is_some() && config.output.include_diffs && diff_options.range.is_none() {
+        if explicit_diff {
+            anyhow::bail!("With --ref, only --diff-range can select the diff");
+        }
+        eprintln!(
+            "Ignoring --include-diffs: with --ref, pass the commits to compare with --diff-range"
+        );
+        config.output.include_diffs = false;
+    }
+    if let Some(count) = cli.file_history {
+        config.output.file_history = count;
+    }
+    if let Some(count) = cli.log_count {
+        config.output.log_count = count;
+    }
+    if let Some(since) = cli.log_since.clone() {
+        config.output.log_since = Some(since);
+    }
+    config.output.log_diffs |= cli.log_diffs;
+    config.output.log_focused |= cli.log_focused;
+    // Like diffs, a log asked for in detail should fail loudly rather than be skipped
+    let explicit_log =
+        cli.log_count.is_some() || cli.log_since.is_some() || cli.log_diffs || cli.log_focused;
+    if cli.include_logs || explicit_log {
         config.output.include_logs = true;
     }
+    if let Some(sort) = cli.sort {
+        config.output.sort = Some(sort);
+    }
+    if let Some(window) = cli.churn_window {
+        config.output.churn_window = window;
+    }
     if let Some(h) = cli.header_text {
         config.output.header_text = Some(h);
     }
@@ -108,6 +241,60 @@ async fn main() -> Result<()> {
             .custom_patterns
             .extend(ign.split(',').map(|s| s.to_string()));
     }
+    if let Some(format) = cli.survey_format {
+        config.prompts.survey_format = format;
+    }
+
+    // A JSON survey reply feeds its patterns into this (phase 2) run
+    let survey_reply = cli
+        .survey_reply
+        .as_deref()
+        .map(survey::load_reply)
+        .transpose()?;
+    let mut focus_patterns: Vec<String> = cli
+        .focus
+        .iter()
+        .flat_map(|f| f.split(','))
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+    if let Some(reply) = &survey_reply {
+        status!(to_stdout, "Survey reply: {}", reply.rationale);
+        focus_patterns.extend(reply.focus.iter().map(|p| p.trim().to_string()));
+        config
+            .ignore
+            .custom_patterns
+            .extend(reply.ignore.iter().map(|p| p.trim().to_string()));
+    }
+
+    // Every style is rendered from the same processed files
+    let mut styles: Vec<OutputStyle> = Vec::new();
+    if config.output.styles.is_empty() {
+        styles.push(config.output.style.clone());
+    }
+    for style in &config.output.styles {
+        if !styles.contains(style) {
+            styles.push(style.clone());
+        }
+    }
+    if to_stdout && cli.split_output.is_some() {
+        anyhow::bail!("--split-output writes numbered part files; pass an output file");
+    }
+    if styles.len() > 1 {
+        if to_stdout {
+            anyhow::bail!("Cannot write several output styles to stdout; pass an output file");
+        }
+        let mut extensions = Vec::new();
+        for style in &styles {
+            let mut style_config = config.clone();
+            style_config.output.style = style.clone();
+            let ext = output::file_extension(&style_config);
+            if extensions.contains(&ext) {
+                anyhow::bail!("Several output styles would write .{} files", ext);
+            }
+            extensions.push(ext);
+        }
+    }
 
     // --- INTENT COLLECTION ---
     // We collect a list of (intent_name, intent_content) tuples.
@@ -122,7 +309,10 @@ async fn main() -> Result<()> {
     }
 
     let mut intent_tasks = Vec::new();
-    let has_focus = cli.focus.is_some();
+    let has_focus = cli.focus.is_some()
+        || survey_reply.is_some()
+        || cli.focus_changed.is_some()
+        || review_args.is_some();
     let mut is_bulk_mode = false;
 
     if let Some(intent_arg) = &cli.intent {
@@ -166,14 +356,62 @@ async fn main() -> Result<()> {
     // 2. Handle Remote
     let temp_dir = tempfile::tempdir()?;
     let mut root_paths = Vec::new();
-
-    if let Some(remote_url) = &cli.remote {
-        let target = temp_dir.path().join("repo");
-        println!("Cloning remote repository...");
-        git::clone_repo(remote_url, &target, cli.remote_branch.as_deref())?;
-        root_paths.push(target);
+    let mut review = None;
+    // The revision packed instead of the working tree: `--ref`, or a review's head
+    let mut git_ref = cli.git_ref.clone();
+    // Keeps a cached remote checkout locked until the pack is written
+    let mut _cache_checkout = None;
+
+    if let Some(remote) = &cli.remote {
+        let spec = remote::parse(remote, cli.remote_branch.as_deref())?;
+        let target = if config.cache.enabled && !cli.no_cache {
+            let cache = RemoteCache::new(&config.cache, cli.cache_dir.as_deref())?;
+            let checkout = cache.checkout(git.as_ref(), &spec, |message| {
+                status!(to_stdout, "{}", message)
+            })?;
+            let repo = checkout.repo.clone();
+            _cache_checkout = Some(checkout);
+            repo
+        } else {
+            let target = temp_dir.path().join("repo");
+            status!(to_stdout, "Cloning remote repository...");
+            let options = git::CloneOptions {
+                rev: spec.rev.clone(),
+                sparse: spec.subdir.clone(),
+            };
+            git.clone_repo(&spec.url, &target, &options)?;
+            target
+        };
+        match &spec.subdir {
+            Some(dir) => {
+                let root = target.join(dir);
+                if !root.is_dir() {
+                    anyhow::bail!("Directory {} not found in {}", dir, spec.url);
+                }
+                root_paths.push(root);
+            }
+            None => root_paths.push(target),
+        }
+    } else if let Some((range, directory)) = &review_args {
+        let dir = fs::canonicalize(directory).unwrap_or_else(|_| PathBuf::from(directory));
+        let prepared = review::prepare(git.as_ref(), &dir, range)?;
+        status!(
+            to_stdout,
+            "Reviewing {} ({} changed file(s) in full)",
+            prepared.range,
+            prepared.changed.len()
+        );
+        root_paths.push(dir);
+        git_ref = Some(prepared.head_commit.clone());
+        focus_patterns.extend(prepared.changed.iter().map(|p| globset::escape(p)));
+        diff_options.range = Some(prepared.commit_range.clone());
+        review = Some(prepared);
     } else {
-        for d in &cli.directories {
+        let directories = match &cli.command {
+            Some(Command::Pack { directories }) => directories,
+            _ => &cli.directories,
+        };
+        for d in directories {
             if let Ok(canon) = fs::canonicalize(d) {
                 root_paths.push(canon);
             } else {
@@ -183,17 +421,21 @@ async fn main() -> Result<()> {
     }
 
     // Focus Logic
+    if let Some(base) = &cli.focus_changed {
+        let base = Some(base.as_str()).filter(|b| !b.is_empty());
+        let changed = git
+            .changed_paths(&root_paths[0], base)
+            .context("--focus-changed needs a git repository")?;
+        status!(to_stdout, "Focusing on {} changed file(s)", changed.len());
+        focus_patterns.extend(changed.iter().map(|p| globset::escape(p)));
+    }
     let mut focus_set_builder = GlobSetBuilder::new();
-    let has_focus_patterns = if let Some(focus_str) = &cli.focus {
-        for pattern in focus_str.split(',') {
-            if let Ok(glob) = Glob::new(pattern.trim()) {
-                focus_set_builder.add(glob);
-            }
+    for pattern in &focus_patterns {
+        if let Ok(glob) = Glob::new(pattern) {
+            focus_set_builder.add(glob);
         }
-        true
-    } else {
-        false
-    };
+    }
+    let has_focus_patterns = has_focus;
     let focus_set = focus_set_builder.build()?;
 
     // 3. File Discovery
@@ -208,6 +450,8 @@ async fn main() -> Result<()> {
     }
 
     builder.git_ignore(config.ignore.use_gitignore);
+    // Never pack our own state directory (pack manifests etc.)
+    builder.filter_entry(|entry| entry.file_name() != state::STATE_DIR_NAME);
 
     let mut overrides = ignore::overrides::OverrideBuilder::new(&root_paths[0]);
 
@@ -223,15 +467,10 @@ async fn main() -> Result<()> {
     }
 
     for pattern in &config.ignore.custom_patterns {
-        // NOTE: The previous code added these without "!", implying Whitelist in OverrideBuilder terms,
-        // but then manually filtered them out with 'custom_ignore_set' later.
-        // To be safe and efficient, we should add them as ignore patterns here too.
-        // However, to minimize side effects on existing logic we will keep the manual filter relies
-        // and just add them here if they were added before.
-        // Actually, if we add them as whitelist here, it might force-include them even if gitignore ignores them?
-        // Let's stick to the previous behavior for 'custom_patterns' (adding them raw) to avoid breaking changes,
-        // although it looks suspicious.
-        overrides.add(pattern)?;
+        // Added as ignore overrides ("!"). Without the prefix an override is a whitelist,
+        // which would drop every file NOT matching the pattern (so `--ignore tests/**`
+        // packed nothing). The manual 'custom_ignore_set' filter below stays as a backstop.
+        overrides.add(&format!("!{}", pattern))?;
     }
 
     if let Some(inc) = &cli.include {
@@ -246,7 +485,8 @@ async fn main() -> Result<()> {
         }
     }
 
-    builder.overrides(overrides.build()?);
+    let overrides = overrides.build()?;
+    builder.overrides(overrides.clone());
 
     // Prepare manual globset for ignore patterns to ensure they work reliably
     let mut glob_builder = GlobSetBuilder::new();
@@ -257,34 +497,84 @@ async fn main() -> Result<()> {
     }
     let custom_ignore_set = glob_builder.build()?;
 
-    let walker = builder.build();
     let mut files_to_process = Vec::new();
-
-    for result in walker {
-        match result {
-            Ok(entry) => {
-                if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
-                    let path = entry.into_path();
-
-                    // Manual check against custom ignore patterns
-                    // We check path relative to the root base
-                    let rel_path =
-                        pathdiff::diff_paths(&path, &root_paths[0]).unwrap_or_else(|| path.clone());
-                    if custom_ignore_set.is_match(&rel_path) {
-                        continue;
+    // Directories seen during the walk, listed in the tree even if none of their files are packed
+    let mut walked_dirs = Vec::new();
+    // File contents read from the object database (`--ref`) instead of the filesystem
+    let mut snapshot_files = None;
+
+    if let Some(rev) = &git_ref {
+        if root_paths.len() > 1 {
+            anyhow::bail!("--ref packs a single directory");
+        }
+        let snapshot = snapshot::load(
+            git.as_ref(),
+            &root_paths[0],
+            rev,
+            &overrides,
+            &custom_ignore_set,
+            config.ignore.use_gitignore,
+            config.ignore.use_default_patterns,
+        )?;
+        files_to_process = snapshot.files.keys().cloned().collect();
+        files_to_process.sort();
+        if config.output.include_empty_directories {
+            walked_dirs = snapshot.dirs;
+        }
+        snapshot_files = Some(Arc::new(snapshot.files));
+    } else {
+        for result in builder.build() {
+            match result {
+                Ok(entry) => {
+                    if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
+                        let path = entry.into_path();
+
+                        // Manual check against custom ignore patterns
+                        // We check path relative to the root base
+                        let rel_path = pathdiff::diff_paths(&path, &root_paths[0])
+                            .unwrap_or_else(|| path.clone());
+                        if custom_ignore_set.is_match(&rel_path) {
+                            continue;
+                        }
+
+                        files_to_process.push(path);
+                    } else if config.output.include_empty_directories
+                        && entry.depth() > 0
+                        && entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false)
+                    {
+                        let rel_path = pathdiff::diff_paths(entry.path(), &root_paths[0])
+                            .unwrap_or_else(|| entry.path().to_path_buf());
+                        if custom_ignore_set.is_match(&rel_path) {
+                            continue;
+                        }
+                        walked_dirs.push(rel_path.to_string_lossy().replace("\\", "/"));
                     }
-
-                    files_to_process.push(path);
                 }
-            }
-            Err(err) => {
-                if cli.verbose {
-                    eprintln!("Error walking: {}", err)
+                Err(err) => {
+                    if cli.verbose {
+                        eprintln!("Error walking: {}", err)
+                    }
                 }
             }
         }
     }
 
+    if let Some(reply) = &survey_reply {
+        let rel_paths: Vec<String> = files_to_process
+            .iter()
+            .map(|p| {
+                pathdiff::diff_paths(p, &root_paths[0])
+                    .unwrap_or_else(|| p.clone())
+                    .to_string_lossy()
+                    .replace("\\", "/")
+            })
+            .collect();
+        if let Err(e) = survey::validate_against_files(reply, &rel_paths) {
+            spinner.finish_and_clear();
+            return Err(e);
+        }
+    }
+
     spinner.set_message(format!(
         "Found {} files. Processing...",
         files_to_process.len()
@@ -292,6 +582,14 @@ async fn main() -> Result<()> {
 
     // 4. Processing
     let processed_files = Arc::new(Mutex::new(Vec::new()));
+    let in_repo = git.is_repo(&root_paths[0]);
+    let sort = config.output.sort.unwrap_or(if in_repo {
+        SortOrder::Churn
+    } else {
+        SortOrder::Path
+    });
+    // What each file imports, read before compression drops it
+    let imports = Arc::new(Mutex::new(HashMap::new()));
     let mut tasks = Vec::new();
     let root_base = root_paths[0].clone();
 
@@ -300,14 +598,21 @@ async fn main() -> Result<()> {
         let processed_files = processed_files.clone();
         let root_base = root_base.clone();
         let focus_set = focus_set.clone();
+        let snapshot_files = snapshot_files.clone();
+        let imports = imports.clone();
 
         tasks.push(tokio::spawn(async move {
-            if let Ok(content_bytes) = fs::read(&path) {
+            let content_bytes = match &snapshot_files {
+                Some(blobs) => blobs.get(&path).cloned(),
+                None => fs::read(&path).ok(),
+            };
+            if let Some(content_bytes) = content_bytes {
                 if fs_tools::is_binary(&content_bytes) {
                     return;
                 }
 
                 let mut content = String::from_utf8_lossy(&content_bytes).to_string();
+                let original_line_count = content.lines().count();
                 let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
 
                 if config.security.enable_security_check && security::is_suspicious(&content) {
@@ -327,8 +632,22 @@ async fn main() -> Result<()> {
                     config.output.compress
                 };
 
+                // One parse serves both the import scan and compression
+                let tree = if sort == SortOrder::Dependency || should_compress_file {
+                    language::compression::parse(&content, ext)
+                } else {
+                    None
+                };
+
+                if sort == SortOrder::Dependency {
+                    let file_imports =
+                        language::imports::local_imports(&rel_path, &content, tree.as_ref());
+                    imports.lock().await.insert(rel_path.clone(), file_imports);
+                }
+
                 if should_compress_file {
-                    if let Some(compressed) = language::compression::compress_content(&content, ext)
+                    if let Some(compressed) =
+                        language::compression::compress_content(&content, ext, tree.as_ref())
                     {
                         content = compressed;
                     }
@@ -366,7 +685,10 @@ async fn main() -> Result<()> {
                     content,
                     char_count,
                     token_count,
+                    original_line_count,
+                    byte_count: content_bytes.len(),
                     is_skeleton: should_compress_file,
+                    truncated: false,
                 });
             }
         }));
@@ -381,172 +703,518 @@ async fn main() -> Result<()> {
     // 5. Sorting & Git
     let mut files = Arc::try_unwrap(processed_files).unwrap().into_inner();
 
-    if git::is_git_repo(&root_paths[0]) {
-        let counts = git::get_file_change_counts(&root_paths[0]);
-        files.sort_by(|a, b| {
-            let count_a = counts.get(&a.path).unwrap_or(&0);
-            let count_b = counts.get(&b.path).unwrap_or(&0);
-            count_a.cmp(count_b)
-        });
+    // A repository without commits has no history to read yet
+    let has_commits = in_repo
+        && (git_ref.is_some()
+            || git
+                .log(
+                    &root_paths[0],
+                    &git::LogOptions {
+                        max_count: Some(1),
+                        ..Default::default()
+                    },
+                )
+                .is_ok());
+    // Without history, an asked-for churn or recency order falls back to path
+    let history_order = match config.output.sort {
+        Some(SortOrder::Churn) => Some("churn"),
+        Some(SortOrder::Recency) => Some("recency"),
+        _ => None,
+    };
+    if let Some(order) = history_order.filter(|_| !has_commits) {
+        eprintln!(
+            "Warning: --sort {} needs a git repository with commits, sorting by path",
+            order
+        );
+    }
+    let needs_last_commits = sort == SortOrder::Recency
+        || config
+            .output
+            .file_metadata
+            .contains(&cli::FileMetadata::Commit);
+    let last_commits = if needs_last_commits && has_commits {
+        let paths: Vec<String> = files.iter().map(|f| f.path.clone()).collect();
+        // Only gathered when `--sort recency` or `commit` metadata asks for it
+        git.last_commits(&root_paths[0], git_ref.as_deref(), &paths)
+            .context("Cannot read the last commit of each file")?
     } else {
-        files.sort_by(|a, b| a.path.cmp(&b.path));
+        HashMap::new()
+    };
+
+    match sort {
+        SortOrder::Churn if has_commits => {
+            let counts = match git.file_change_counts(
+                &root_paths[0],
+                git_ref.as_deref(),
+                config.output.churn_window,
+            ) {
+                Ok(counts) => counts,
+                // Churn is only the default order inside a repository; asked for, it must work
+                Err(e) if config.output.sort.is_some() => {
+                    return Err(e.context("Cannot count file changes for --sort churn"))
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Warning: cannot count file changes, sorting by path: {:#}",
+                        e
+                    );
+                    HashMap::new()
+                }
+            };
+            ordering::by_churn(&mut files, &counts);
+        }
+        SortOrder::Recency if has_commits => ordering::by_recency(&mut files, &last_commits),
+        SortOrder::Tokens => ordering::by_tokens(&mut files),
+        SortOrder::Dependency => {
+            let imports = Arc::try_unwrap(imports).unwrap().into_inner();
+            ordering::by_dependency(&mut files, &imports);
+        }
+        // Relevance depends on the intent, and is applied per intent below
+        _ => ordering::by_path(&mut files),
+    }
+
+    if let Some(reply) = &survey_reply {
+        let missing = survey::missing_symbols(reply, &files);
+        if !missing.is_empty() {
+            eprintln!(
+                "Warning: survey reply symbols not found in any focused file: {}",
+                missing.join(", ")
+            );
+        }
     }
 
     let git_diff = if config.output.include_diffs {
-        git::get_diffs(&root_paths[0]).ok()
+        match git.diffs(&root_paths[0], &diff_options) {
+            Ok(diffs) => Some(diffs),
+            Err(e) if explicit_diff => return Err(e),
+            Err(_) => None,
+        }
     } else {
         None
     };
 
-    let git_log = if config.output.include_logs {
-        git::get_logs(&root_paths[0]).ok()
+    // Without focus patterns there is nothing to restrict the log to
+    if config.output.log_focused && !has_focus && config.output.include_logs {
+        eprintln!("Warning: --log-focused has no effect without --focus, logging every commit");
+    }
+    let log_options = git::LogOptions {
+        rev: git_ref.clone(),
+        max_count: Some(config.output.log_count),
+        since: config.output.log_since.clone(),
+        paths: if config.output.log_focused && has_focus {
+            Some(
+                files
+                    .iter()
+                    .filter(|f| !f.is_skeleton)
+                    .map(|f| f.path.clone())
+                    .collect(),
+            )
+        } else {
+            None
+        },
+        patch_paths: config
+            .output
+            .log_diffs
+            .then(|| files.iter().map(|f| f.path.clone()).collect()),
+        reverse: false,
+    };
+    let git_log = if let Some(review) = &review {
+        // Every commit of the range, oldest first
+        let options = git::LogOptions {
+            rev: Some(review.commit_range.clone()),
+            max_count: None,
+            since: None,
+            reverse: true,
+            ..log_options
+        };
+        Some(git.log(&root_paths[0], &options)?)
+    } else if config.output.include_logs {
+        match git.log(&root_paths[0], &log_options) {
+            Ok(commits) => Some(commits),
+            Err(e) if explicit_log => return Err(e),
+            Err(_) => None,
+        }
     } else {
         None
     };
 
+    // Who changed the focused files lately, and which of their lines
+    let file_history = if config.output.file_history > 0 && has_commits && !has_focus {
+        eprintln!("Warning: --file-history only covers focused files, and there is no --focus");
+        None
+    } else if config.output.file_history > 0 && has_commits {
+        let mut tasks = Vec::new();
+        for file in files.iter().filter(|f| !f.is_skeleton) {
+            let git = git.clone();
+            let root = root_paths[0].clone();
+            let path = file.path.clone();
+            let rev = git_ref.clone();
+            let count = config.output.file_history;
+            tasks.push(tokio::task::spawn_blocking(move || {
+                let history = git.file_history(&root, &path, rev.as_deref(), count);
+                (path, history)
+            }));
+        }
+        let mut histories = Vec::new();
+        for task in tasks {
+            match task.await? {
+                (_, Ok(history)) if !history.commits.is_empty() => histories.push(history),
+                (_, Ok(_)) => {}
+                (path, Err(e)) => {
+                    eprintln!("Warning: cannot read the history of {}: {:#}", path, e)
+                }
+            }
+        }
+        Some(histories)
+    } else {
+        None
+    };
+
+    let review_summary = match &review {
+        Some(review) => {
+            Some(review.summarize(git.as_ref(), git_diff.as_deref().unwrap_or_default())?)
+        }
+        None => None,
+    };
+
     // --- OUTPUT GENERATION LOOP ---
 
-    // If no intents, we run once with default config
+    // If no intents, we run once with default config (a review always gets its prompt)
     if intent_tasks.is_empty() {
         intent_tasks.push(IntentTask {
             name: "default".to_string(),
-            content: String::new(),
+            content: match &review {
+                Some(review) => format!("Review the changes in {}.", review.range),
+                None => String::new(),
+            },
         });
     }
 
     let total_tokens: usize = files.iter().map(|f| f.token_count).sum();
     let multi_output = intent_tasks.len() > 1 || is_bulk_mode;
+    let current_manifest = PackManifest::from_files(&files);
 
     for task in &intent_tasks {
-        let mut task_config = config.clone();
-
-        // Construct header with intent
-        let mut generated_header = String::new();
-        if !task.content.is_empty() {
-            if !has_focus {
-                // PHASE 1: SURVEY
-                generated_header.push_str("\n");
-                // 1. User Request
-                generated_header.push_str("<user_request>\n");
-                generated_header.push_str(&format!("{}\n", task.content));
-                generated_header.push_str("</user_request>\n\n");
-
-                // 2. Instructions
-                generated_header.push_str("<instruction>\n");
-                generated_header.push_str("THE USER WANTS TO: The user wants to achieve the goal described in the <user_request> above.\n\n");
-                generated_header.push_str("Attached is the SKELETON of the codebase.\n");
-                generated_header.push_str("Your job is to analyze this structure and identify which files are crucial to implement the request.\n");
-                generated_header.push_str("You are a Context Engineer. Your goal is to construct the CLI command for the next phase (Phase 2) that carefully isolates the relevant code while excluding noise.\n\n");
-                generated_header.push_str("## Tool Reference: rustymix\n");
-                generated_header
-                    .push_str("rustymix packs a codebase into a single context file.\n");
-                generated_header.push_str("- `--focus \"pattern1,pattern2\"`: Critical files/directories to read in FULL TEXT. Supports globs (e.g., `src/core/**`).\n");
-                generated_header.push_str("- `--ignore \"pattern1,pattern2\"`: Files/directories to completely EXCLUDE from the pack (e.g., `tests/**`, `legacy_crate/**`).\n\n");
-                generated_header.push_str("## Strategy\n");
-                generated_header
-                    .push_str("- Use globs (`**`) to select entire relevant directories.\n");
-                generated_header
-                    .push_str("- Exclude unrelated crates or directories to save tokens.\n");
-                generated_header.push_str(
-                    "- Focus on interfaces and definitions first if the task is exploratory.\n\n",
-                );
-                generated_header.push_str("## Task\n");
-                generated_header.push_str("Based on the user's intent and the attached skeleton, return a SINGLE LINE containing the optimized `rustymix` command arguments.\n");
-                generated_header.push_str(
-                    "Example: `--focus \"src/auth/**,src/main.rs\" --ignore \"tests/**\"`\n",
-                );
-                generated_header.push_str("DO NOT provide explanations. Just the arguments.\n");
-                generated_header.push_str("</instruction>\n");
-            } else {
-                // PHASE 2: BUILD
-                generated_header.push_str("\n");
-                // 1. User Request
-                generated_header.push_str("<user_request>\n");
-                generated_header.push_str(&format!("{}\n", task.content));
-                generated_header.push_str("</user_request>\n\n");
-
-                // 2. Instructions
-                generated_header.push_str("<instruction>\n");
-                generated_header.push_str("THE USER WANTS TO: The user wants to achieve the goal described in the <user_request> above.\n\n");
-                generated_header.push_str("Attached is the CONTEXT PACK.\n");
-                generated_header.push_str(
-                    "- Files marked 'mode=\"full\"' are the specific files you requested.\n",
-                );
-                generated_header.push_str("- Files marked 'mode=\"skeleton\"' are compressed context to prevent hallucinations.\n");
-                generated_header
-                    .push_str("Please implement the requested changes based on this context.\n");
-                generated_header.push_str("</instruction>\n");
+        // Delta packing: compare against the manifest of this intent's previous pack
+        let changes = if cli.since_last {
+            match state::load_manifest(&root_paths[0], &task.name) {
+                Ok(Some(previous)) => Some(current_manifest.diff(&previous)),
+                Ok(None) => {
+                    status!(
+                        to_stdout,
+                        "No previous pack found for '{}', emitting a full pack.",
+                        task.name
+                    );
+                    None
+                }
+                Err(e) => {
+                    eprintln!("Ignoring previous pack manifest: {:#}", e);
+                    None
+                }
             }
+        } else {
+            None
+        };
+        let mut task_files = match &changes {
+            Some(c) => Cow::Owned(
+                files
+                    .iter()
+                    .filter(|f| c.includes(&f.path))
+                    .cloned()
+                    .collect(),
+            ),
+            None => Cow::Borrowed(files.as_slice()),
+        };
+        if sort == SortOrder::Relevance {
+            ordering::by_relevance(task_files.to_mut(), &task.content);
         }
+        let task_files: &[ProcessedFile] = &task_files;
+
+        // Mark the diffs whose file is in this task's pack
+        let task_paths: HashSet<&str> = task_files.iter().map(|f| f.path.as_str()).collect();
+        let git_diff: Option<Vec<FileDiff>> = git_diff.as_ref().map(|diffs| {
+            diffs
+                .iter()
+                .map(|d| FileDiff {
+                    in_pack: task_paths.contains(d.path.as_str()),
+                    ..d.clone()
+                })
+                .collect()
+        });
 
-        if let Some(existing) = task_config.output.header_text {
-            task_config.output.header_text = Some(format!("{}\n{}", existing, generated_header));
-        } else if !generated_header.is_empty() {
-            task_config.output.header_text = Some(generated_header);
-        }
-
-        let output_string = output::generate_output(
-            &files,
-            &task_config,
-            git_diff.as_deref(),
-            git_log.as_deref(),
-        );
+        // Instructions for the intent, rendered apart from the user's header
+        let prompt = if task.content.is_empty() {
+            None
+        } else {
+            // PHASE 1 (survey) without focus patterns, PHASE 2 (build) with them
+            let phase = if review_summary.is_some() {
+                PromptPhase::Review
+            } else if has_focus {
+                PromptPhase::Build
+            } else {
+                PromptPhase::Survey
+            };
+            let mut vars = PromptVars::new(&task.content, &focus_patterns, task_files);
+            vars.review = review_summary.as_ref();
+            vars.mode_marked = config
+                .output
+                .file_metadata
+                .contains(&cli::FileMetadata::Mode);
+            Some(prompts::render(phase, &config.prompts, &vars)?)
+        };
 
-        // Determine output path
-        let out_path = if multi_output {
-            // If multiple intents, we likely want to output to a specific directory or format filenames
-            // "rustymix-output-intentName.xml"
-            let base_dir = if let Some(out_arg) = &cli.output {
-                if Path::new(out_arg).is_dir() {
-                    PathBuf::from(out_arg)
+        let tree = DirectoryTree::build(task_files, &walked_dirs);
+        let intent = Some(task.content.as_str()).filter(|c| !c.is_empty());
+
+        for (style_index, style) in styles.iter().enumerate() {
+            let mut task_config = config.clone();
+            task_config.output.style = style.clone();
+
+            // Determine output path
+            let out_path = if multi_output {
+                // If multiple intents, we likely want to output to a specific directory or format filenames
+                // "rustymix-output-intentName.xml"
+                let base_dir = if let Some(out_arg) = &cli.output {
+                    if Path::new(out_arg).is_dir() {
+                        PathBuf::from(out_arg)
+                    } else {
+                        // If output arg is a file but we have multiple outputs, we fallback to parent dir
+                        PathBuf::from(out_arg)
+                            .parent()
+                            .map(|p| p.to_path_buf())
+                            .unwrap_or_else(|| PathBuf::from("."))
+                    }
                 } else {
-                    // If output arg is a file but we have multiple outputs, we fallback to parent dir
-                    PathBuf::from(out_arg)
-                        .parent()
-                        .map(|p| p.to_path_buf())
-                        .unwrap_or_else(|| PathBuf::from("."))
-                }
+                    PathBuf::from(".")
+                };
+
+                let ext = output::file_extension(&task_config);
+
+                base_dir.join(format!("rustymix-{}.{}", task.name, ext))
+            } else if styles.len() > 1 {
+                // One file per style next to the requested output: pack.xml, pack.md, ...
+                PathBuf::from(&task_config.output.file_path)
+                    .with_extension(output::file_extension(&task_config))
             } else {
-                PathBuf::from(".")
+                PathBuf::from(&task_config.output.file_path)
             };
 
-            let ext = match task_config.output.style {
-                OutputStyle::Xml => "xml",
-                OutputStyle::Markdown => "md",
-                OutputStyle::Json => "json",
-                OutputStyle::Plain => "txt",
-            };
+            // With several styles the first one listed goes to the clipboard
+            let copy_to_clipboard =
+                task_config.output.copy_to_clipboard && !multi_output && style_index == 0;
+            let pack_to_stdout = to_stdout && !multi_output;
+
+            // Render: either one pack, or self-contained numbered parts within a token budget
+            if let Some(budget) = cli.split_output {
+                // Header of part `index`: part notice everywhere, user header on the first
+                // part; a pack that fits in one part is written as it is
+                let part_config = |index: usize, total: usize, continued: &[String]| {
+                    let mut part_config = task_config.clone();
+                    if total == 1 {
+                        return part_config;
+                    }
+                    let mut header = split::part_header(index, total, continued);
+                    if index == 0 {
+                        if let Some(existing) = &task_config.output.header_text {
+                            header = format!("{}\n{}", existing, header);
+                        }
+                    }
+                    part_config.output.header_text = Some(header);
+                    part_config
+                };
 
-            base_dir.join(format!("rustymix-{}.{}", task.name, ext))
-        } else {
-            PathBuf::from(&task_config.output.file_path)
-        };
+                // Part `index`: the change summary and full tree go to the first part, the
+                // diff, log, intent and prompt to the last one
+                let render_part = |parts: &[split::Part], index: usize, out: &mut dyn Write| {
+                    let total = parts.len();
+                    let part = &parts[index];
+                    let is_first = index == 0;
+                    let is_last = index + 1 == total;
+                    let part_tree = (!is_first).then(|| DirectoryTree::build(&part.files, &[]));
+                    let ctx = PackContext {
+                        git_diff: git_diff.as_deref().filter(|_| is_last),
+                        git_log: git_log.as_deref().filter(|_| is_last),
+                        changes: changes.as_ref().filter(|_| is_first),
+                        tree: part_tree.as_ref().unwrap_or(&tree),
+                        intent: intent.filter(|_| is_last),
+                        prompt: prompt.as_deref().filter(|_| is_last),
+                        last_commits: &last_commits,
+                        file_history: file_history.as_deref(),
+                    };
+                    let config = part_config(index, total, &part.continued);
+                    output::write_output(&part.files, &config, &ctx, out)
+                };
+
+                // Start from the budget left once the fixed sections are rendered, then
+                // render every part and shrink the file budget by the largest excess
+                // (summaries, split notes, wrappers) until every part fits
+                let overhead = fs_tools::count_tokens(&output::generate_output(
+                    &[],
+                    &part_config(0, 2, &[]),
+                    &PackContext {
+                        git_diff: git_diff.as_deref(),
+                        git_log: git_log.as_deref(),
+                        changes: changes.as_ref(),
+                        tree: &tree,
+                        intent,
+                        prompt: prompt.as_deref(),
+                        last_commits: &last_commits,
+                        file_history: file_history.as_deref(),
+                    },
+                )?);
+                let too_small = |needed: usize| {
+                    anyhow::anyhow!(
+                        "--split-output {} is too small: a part needs at least {} tokens",
+                        budget,
+                        needed
+                    )
+                };
+                let mut file_budget = budget.checked_sub(overhead).ok_or(too_small(overhead))?;
+                let parts = loop {
+                    let parts = split::split_files(task_files, file_budget);
+                    let mut largest = 0;
+                    for index in 0..parts.len() {
+                        let mut rendered = Vec::new();
+                        render_part(&parts, index, &mut rendered)?;
+                        largest = largest
+                            .max(fs_tools::count_tokens(&String::from_utf8_lossy(&rendered)));
+                    }
+                    if largest <= budget {
+                        break parts;
+                    }
+                    let excess = largest - budget;
+                    if excess >= file_budget {
+                        return Err(too_small(largest));
+                    }
+                    file_budget -= excess;
+                };
 
-        // Write
-        if task_config.output.copy_to_clipboard && !multi_output {
-            if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                let _ = clipboard.set_text(&output_string);
-                println!("Output copied to clipboard!");
+                let total = parts.len();
+                if copy_to_clipboard && total > 1 {
+                    status!(
+                        to_stdout,
+                        "Output is split into {} parts; skipping clipboard copy.",
+                        total
+                    );
+                }
+                for index in 0..total {
+                    let part_path = if total > 1 {
+                        split::part_path(&out_path, index)
+                    } else {
+                        out_path.clone()
+                    };
+                    emit(
+                        &part_path,
+                        pack_to_stdout,
+                        copy_to_clipboard && total == 1,
+                        |out| render_part(&parts, index, out),
+                    )?;
+                }
+            } else {
+                let ctx = PackContext {
+                    git_diff: git_diff.as_deref(),
+                    git_log: git_log.as_deref(),
+                    changes: changes.as_ref(),
+                    tree: &tree,
+                    intent,
+                    prompt: prompt.as_deref(),
+                    last_commits: &last_commits,
+                    file_history: file_history.as_deref(),
+                };
+                emit(&out_path, pack_to_stdout, copy_to_clipboard, |out| {
+                    output::write_output(task_files, &task_config, &ctx, out)
+                })?;
             }
         }
 
-        if cli.output.as_deref() == Some("-") && !multi_output {
-            print!("{}", output_string);
-        } else {
-            if let Some(parent) = out_path.parent() {
-                fs::create_dir_all(parent)?;
+        if let Some(c) = &changes {
+            status!(to_stdout, "Changes since last pack: {}", c.headline());
+        }
+        // Only recorded when asked for, so plain packs leave the scanned tree untouched
+        if cli.since_last {
+            if let Err(e) = state::save_manifest(&root_paths[0], &task.name, &current_manifest) {
+                eprintln!("Warning: failed to record the pack manifest: {:#}", e);
             }
-            fs::write(&out_path, &output_string)?;
-            println!("Output written to {}", out_path.display());
         }
     }
 
     if multi_output {
-        println!("Processed {} intents.", intent_tasks.len());
+        status!(to_stdout, "Processed {} intents.", intent_tasks.len());
     }
 
-    println!("Total Files: {}", files.len());
-    println!("Total Tokens: {}", total_tokens);
+    status!(to_stdout, "Total Files: {}", files.len());
+    status!(to_stdout, "Total Tokens: {}", total_tokens);
+
+    let top_n = config.output.top_files_length;
+    if top_n > 0 && !files.is_empty() {
+        status!(to_stdout, "\nTop {} Files by Tokens:", top_n);
+        for (i, t) in output::top_files(&files, top_n, |f| f.token_count)
+            .iter()
+            .enumerate()
+        {
+            status!(
+                to_stdout,
+                "{:>3}. {} ({} tokens, {:.1}%)",
+                i + 1,
+                t.path,
+                t.count,
+                t.share
+            );
+        }
+        status!(to_stdout, "\nTop {} Files by Characters:", top_n);
+        for (i, t) in output::top_files(&files, top_n, |f| f.char_count)
+            .iter()
+            .enumerate()
+        {
+            status!(
+                to_stdout,
+                "{:>3}. {} ({} chars, {:.1}%)",
+                i + 1,
+                t.path,
+                t.count,
+                t.share
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes one pack (or part) to `path`, or to stdout for `-o -`. Output is streamed
+/// through a buffered writer; it is only rendered into memory when it also has to be
+/// copied to the clipboard.
+fn emit(
+    path: &Path,
+    to_stdout: bool,
+    copy_to_clipboard: bool,
+    render: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    let mut sink: Box<dyn Write> = if to_stdout {
+        Box::new(BufWriter::new(io::stdout().lock()))
+    } else {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Box::new(BufWriter::new(fs::File::create(path).with_context(
+            || format!("Failed to create output file {}", path.display()),
+        )?))
+    };
+
+    if copy_to_clipboard {
+        let mut buffer = Vec::new();
+        render(&mut buffer)?;
+        if let Ok(mut clipboard) = arboard::Clipboard::new() {
+            let _ = clipboard.set_text(String::from_utf8_lossy(&buffer));
+            status!(to_stdout, "Output copied to clipboard!");
+        }
+        sink.write_all(&buffer)?;
+    } else {
+        render(&mut sink)?;
+    }
+    sink.flush()?;
 
+    if !to_stdout {
+        println!("Output written to {}", path.display());
+    }
     Ok(())
 }
@@ -0,0 +1,195 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::git::LastCommit;
+use crate::language::imports::Import;
+use crate::output::ProcessedFile;
+
+// Every order puts what matters most last, closest to the question, and breaks ties by
+// path so packs of the same tree are identical.
+
+pub fn by_path(files: &mut [ProcessedFile]) {
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+}
+
+/// Least changed first, by commit count over the churn window.
+pub fn by_churn(files: &mut [ProcessedFile], counts: &HashMap<String, usize>) {
+    files.sort_by_cached_key(|f| (counts.get(&f.path).copied().unwrap_or(0), f.path.clone()));
+}
+
+/// Least recently committed first; files without a commit (untracked) count as newest.
+pub fn by_recency(files: &mut [ProcessedFile], last_commits: &HashMap<String, LastCommit>) {
+    files.sort_by_cached_key(|f| {
+        let time = last_commits.get(&f.path).map_or(i64::MAX, |c| c.time);
+        (time, f.path.clone())
+    });
+}
+
+/// Smallest first.
+pub fn by_tokens(files: &mut [ProcessedFile]) {
+    files.sort_by_cached_key(|f| (f.token_count, f.path.clone()));
+}
+
+/// Words of the intent too common to say anything about a file.
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "with", "that", "this", "from", "into", "are", "was", "not", "but", "all",
+    "any", "can", "should", "would", "when", "where", "which", "how", "what", "why", "add", "make",
+    "use", "get", "set", "new", "fix",
+];
+
+/// Files packed in full after skeletons; within each, by how many of the intent's words
+/// the path mentions, then how many the content does.
+pub fn by_relevance(files: &mut [ProcessedFile], intent: &str) {
+    let intent = intent.to_lowercase();
+    let mut terms: Vec<&str> = intent
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|t| t.len() >= 3 && !STOP_WORDS.contains(t))
+        .collect();
+    terms.sort_unstable();
+    terms.dedup();
+
+    files.sort_by_cached_key(|f| {
+        let path = f.path.to_lowercase();
+        let content = f.content.to_lowercase();
+        let path_hits = terms.iter().filter(|t| path.contains(*t)).count();
+        let content_hits = terms.iter().filter(|t| content.contains(*t)).count();
+        (!f.is_skeleton, path_hits, content_hits, f.path.clone())
+    });
+}
+
+/// Dependencies before the files importing them, so each file's context precedes it.
+/// Files importing each other (a cycle) are kept together, ordered by path.
+pub fn by_dependency(files: &mut [ProcessedFile], imports: &HashMap<String, Vec<Import>>) {
+    let paths: Vec<&str> = files
+        .iter()
+        .map(|f| f.path.as_str())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let index: HashMap<&str, usize> = paths.iter().enumerate().map(|(i, &p)| (p, i)).collect();
+    let mut by_dir: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, path) in paths.iter().enumerate() {
+        let dir = path.rsplit_once('/').map_or("", |(d, _)| d);
+        by_dir.entry(dir).or_default().push(i);
+    }
+    // Every path under each of its suffixes (`a/b.py`, `b.py`), for Python modules
+    let mut by_suffix: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, path) in paths.iter().enumerate() {
+        for suffix in suffixes(path) {
+            by_suffix.entry(suffix).or_default().push(i);
+        }
+    }
+
+    let mut deps: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); paths.len()];
+    for (i, path) in paths.iter().enumerate() {
+        for import in imports.get(*path).into_iter().flatten() {
+            match import {
+                Import::File(file) => deps[i].extend(index.get(file.as_str())),
+                Import::Module(module) => {
+                    deps[i].extend(by_suffix.get(module.as_str()).into_iter().flatten())
+                }
+                Import::Package(package) => {
+                    for dir in suffixes(package).filter(|d| !d.is_empty()) {
+                        deps[i].extend(by_dir.get(dir).into_iter().flatten());
+                    }
+                }
+            }
+        }
+        deps[i].remove(&i);
+    }
+
+    // Condense cycles into components, then place components dependencies first; among
+    // ready components, the one with the smallest path goes first
+    let component = components(&deps);
+    let count = component.iter().max().map_or(0, |&c| c + 1);
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); count];
+    for (i, &c) in component.iter().enumerate() {
+        members[c].push(i);
+    }
+    let mut unplaced = vec![BTreeSet::new(); count];
+    let mut dependents = vec![BTreeSet::new(); count];
+    for (i, file_deps) in deps.iter().enumerate() {
+        for &d in file_deps {
+            if component[i] != component[d] {
+                unplaced[component[i]].insert(component[d]);
+                dependents[component[d]].insert(component[i]);
+            }
+        }
+    }
+    // Members are in path order, so a component's first member is its smallest path
+    let mut ready: BTreeSet<(usize, usize)> = (0..count)
+        .filter(|&c| unplaced[c].is_empty())
+        .map(|c| (members[c][0], c))
+        .collect();
+    let mut position = HashMap::with_capacity(paths.len());
+    while let Some((_, c)) = ready.pop_first() {
+        for &i in &members[c] {
+            position.insert(paths[i].to_string(), position.len());
+        }
+        for &dependent in &dependents[c] {
+            unplaced[dependent].remove(&c);
+            if unplaced[dependent].is_empty() {
+                ready.insert((members[dependent][0], dependent));
+            }
+        }
+    }
+    files.sort_by_key(|f| position[&f.path]);
+}
+
+/// `path` and every part of it after a `/`: `a/b/c`, `b/c`, `c`.
+fn suffixes(path: &str) -> impl Iterator<Item = &str> {
+    std::iter::once(path).chain(path.match_indices('/').map(move |(i, _)| &path[i + 1..]))
+}
+
+/// Strongly connected component of every node (Kosaraju), numbered from 0.
+fn components(edges: &[BTreeSet<usize>]) -> Vec<usize> {
+    let n = edges.len();
+    // Nodes in order of DFS completion
+    let mut finished = Vec::with_capacity(n);
+    let mut visited = vec![false; n];
+    for start in 0..n {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![(start, edges[start].iter())];
+        while let Some((node, children)) = stack.last_mut() {
+            match children.next() {
+                Some(&child) if !visited[child] => {
+                    visited[child] = true;
+                    stack.push((child, edges[child].iter()));
+                }
+                Some(_) => {}
+                None => {
+                    finished.push(*node);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    let mut reversed = vec![Vec::new(); n];
+    for (from, targets) in edges.iter().enumerate() {
+        for &to in targets {
+            reversed[to].push(from);
+        }
+    }
+    let mut component = vec![usize::MAX; n];
+    let mut count = 0;
+    for &start in finished.iter().rev() {
+        if component[start] != usize::MAX {
+            continue;
+        }
+        let mut stack = vec![start];
+        component[start] = count;
+        while let Some(node) = stack.pop() {
+            for &next in &reversed[node] {
+                if component[next] == usize::MAX {
+                    component[next] = count;
+                    stack.push(next);
+                }
+            }
+        }
+        count += 1;
+    }
+    component
+}
@@ -1,169 +1,220 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs;
+use std::io::Write;
 use std::path::Path;
+use anyhow::{Context, Result};
 use serde::Serialize;
 use crate::config::RustymixConfig;
 use crate::cli::OutputStyle;
+use crate::git::{Commit, FileDiff, FileHistory, LastCommit};
+use crate::language;
+use crate::state::ChangeSummary;
+use crate::template::{self, ChangesView, FileList, GeneratorView, PackView, SummaryView};
+use crate::tree::{DirectoryTree, TreeEntry};
 
-#[derive(Debug)]
+/// Bumped whenever the shape of the JSON output changes incompatibly.
+pub const JSON_SCHEMA_VERSION: u32 = 2;
+
+#[derive(Debug, Clone)]
 pub struct ProcessedFile {
     pub path: String,
     pub content: String,
     pub char_count: usize,
     pub token_count: usize,
+    // Line count of the file on disk, before compression/comment removal
+    pub original_line_count: usize,
+    // Size of the file on disk in bytes
+    pub byte_count: usize,
     // Track if this file is full text (focus) or skeleton (compressed context)
     pub is_skeleton: bool,
+    // Only part of the file's lines are in this content (a piece of a split pack)
+    pub truncated: bool,
 }
 
-pub fn generate_output(
-    files: &[ProcessedFile],
-    config: &RustymixConfig,
-    git_diff: Option<&str>,
-    git_log: Option<&str>
-) -> String {
-    match config.output.style {
-        OutputStyle::Xml => generate_xml(files, config, git_diff, git_log),
-        OutputStyle::Markdown => generate_markdown(files, config, git_diff, git_log),
-        OutputStyle::Json => generate_json(files, config, git_diff, git_log),
-        OutputStyle::Plain => generate_plain(files, config, git_diff, git_log),
+impl ProcessedFile {
+    pub fn mode(&self) -> &'static str {
+        if self.is_skeleton { "skeleton" } else { "full" }
     }
 }
 
-fn generate_xml(files: &[ProcessedFile], config: &RustymixConfig, diff: Option<&str>, log: Option<&str>) -> String {
-    let mut out = String::new();
-    out.push_str("<rustymix>\n");
-
-    if let Some(h) = &config.output.header_text {
-        out.push_str(&format!("<header>{}</header>\n", h));
-    }
-
-    out.push_str("<summary>\n");
-    out.push_str("  This file is a merged representation of the codebase.\n");
-    if let Some(inst) = &config.output.instruction_file_path {
-        if let Ok(c) = fs::read_to_string(inst) {
-            out.push_str(&format!("<instruction>{}</instruction>\n", c));
-        }
-    }
-    out.push_str("</summary>\n");
-
-    out.push_str("<directory_structure>\n");
-    for f in files {
-        out.push_str(&format!("  {}\n", f.path));
-    }
-    out.push_str("</directory_structure>\n");
-
-    out.push_str("<files>\n");
-    for f in files {
-        let mode = if f.is_skeleton { "skeleton" } else { "full" };
-        out.push_str(&format!("<file path=\"{}\" mode=\"{}\">\n", f.path, mode));
-        let content = f.content.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;");
-        out.push_str(&content);
-        out.push_str("\n</file>\n");
-    }
-    out.push_str("</files>\n");
-
-    if let Some(d) = diff {
-        out.push_str("<git_diff>\n");
-        out.push_str(d);
-        out.push_str("\n</git_diff>\n");
-    }
-
-    if let Some(l) = log {
-        out.push_str("<git_log>\n");
-        out.push_str(l);
-        out.push_str("\n</git_log>\n");
-    }
-
-    out.push_str("</rustymix>");
-    out
+/// Everything besides the files themselves that goes into a pack.
+pub struct PackContext<'a> {
+    pub git_diff: Option<&'a [FileDiff]>,
+    pub git_log: Option<&'a [Commit]>,
+    pub changes: Option<&'a ChangeSummary>,
+    pub tree: &'a DirectoryTree,
+    pub intent: Option<&'a str>,
+    /// The instructions generated for the intent, kept apart from the user's header.
+    pub prompt: Option<&'a str>,
+    /// Last commit per file path, filled when `commit` metadata is requested.
+    pub last_commits: &'a HashMap<String, LastCommit>,
+    /// Recent history per file, when `--file-history` is on.
+    pub file_history: Option<&'a [FileHistory]>,
 }
 
-fn generate_markdown(files: &[ProcessedFile], config: &RustymixConfig, diff: Option<&str>, log: Option<&str>) -> String {
-    let mut out = String::new();
-
-    if let Some(h) = &config.output.header_text {
-        out.push_str(&format!("# {}\n\n", h));
-    }
-
-    out.push_str("# File Summary\n\n");
-    out.push_str("This file is a merged representation of the codebase.\n\n");
-
-    out.push_str("# Directory Structure\n\n```\n");
-    for f in files {
-        out.push_str(&format!("{}\n", f.path));
-    }
-    out.push_str("```\n\n");
-
-    out.push_str("# Files\n\n");
-    for f in files {
-        let mode = if f.is_skeleton { "SKELETON (Context Only)" } else { "FULL TEXT" };
-        out.push_str(&format!("## File: {} [{}]\n", f.path, mode));
-        let ext = Path::new(&f.path).extension().and_then(|s| s.to_str()).unwrap_or("");
-        out.push_str(&format!("```{}\n", ext));
-        out.push_str(&f.content);
-        out.push_str("\n```\n\n");
-    }
-
-    if let Some(d) = diff {
-        out.push_str("# Git Diff\n\n```diff\n");
-        out.push_str(d);
-        out.push_str("\n```\n\n");
-    }
-
-    if let Some(l) = log {
-        out.push_str("# Git Log\n\n");
-        out.push_str(l);
-        out.push_str("\n\n");
-    }
-
-    out
+/// A file ranked by one metric, with its share of the pack-wide total.
+#[derive(Debug, Serialize)]
+pub struct TopFile<'a> {
+    pub path: &'a str,
+    pub count: usize,
+    pub share: f64,
 }
 
-fn generate_plain(files: &[ProcessedFile], config: &RustymixConfig, diff: Option<&str>, log: Option<&str>) -> String {
-    let mut out = String::new();
-    let sep = "=".repeat(40);
+/// Returns the `n` largest files by `metric`, ties broken by path.
+pub fn top_files(files: &[ProcessedFile], n: usize, metric: fn(&ProcessedFile) -> usize) -> Vec<TopFile<'_>> {
+    let total: usize = files.iter().map(metric).sum();
+    let mut ranked: Vec<&ProcessedFile> = files.iter().collect();
+    ranked.sort_by(|a, b| metric(b).cmp(&metric(a)).then_with(|| a.path.cmp(&b.path)));
+    ranked
+        .into_iter()
+        .take(n)
+        .map(|f| TopFile {
+            path: &f.path,
+            count: metric(f),
+            share: if total == 0 { 0.0 } else { metric(f) as f64 * 100.0 / total as f64 },
+        })
+        .collect()
+}
 
-    out.push_str(&format!("{}\nRUSTYMIX OUTPUT\n{}\n\n", sep, sep));
+/// Renders the pack into a string (for the clipboard and size estimates).
+pub fn generate_output(files: &[ProcessedFile], config: &RustymixConfig, ctx: &PackContext) -> Result<String> {
+    let mut out = Vec::new();
+    write_output(files, config, ctx, &mut out)?;
+    String::from_utf8(out).context("Rendered output is not valid UTF-8")
+}
 
-    if let Some(h) = &config.output.header_text {
-        out.push_str(&format!("HEADER\n{}\n\n", h));
-    }
+/// Writes the pack into `out` as it is rendered: the header, then each file in pack
+/// order, then the footer. The processed files are already in memory; their rendered
+/// text is not kept, and only the file being written is converted for the template or
+/// serializer.
+pub fn write_output(files: &[ProcessedFile], config: &RustymixConfig, ctx: &PackContext, out: &mut dyn Write) -> Result<()> {
+    let view = PackView::new(files, config, ctx);
+    let source = match config.output.style {
+        OutputStyle::Xml => Cow::Borrowed(template::XML_TEMPLATE),
+        OutputStyle::Markdown => Cow::Borrowed(template::MARKDOWN_TEMPLATE),
+        OutputStyle::Plain => Cow::Borrowed(template::PLAIN_TEMPLATE),
+        OutputStyle::Html => Cow::Borrowed(template::HTML_TEMPLATE),
+        OutputStyle::Json => {
+            serde_json::to_writer_pretty(&mut *out, &Document::new(&view))?;
+            return Ok(());
+        }
+        OutputStyle::Yaml => {
+            serde_norway::to_writer(&mut *out, &Document::new(&view))?;
+            return Ok(());
+        }
+        OutputStyle::Template => {
+            let path = config
+                .output
+                .template_path
+                .as_deref()
+                .context("The template style requires --template <FILE> (or output.templatePath)")?;
+            Cow::Owned(template::load(path)?)
+        }
+        OutputStyle::Jsonl => return write_jsonl(files, config, out),
+    };
+    template::render_pack(&source, view, out)
+}
 
-    for f in files {
-        out.push_str(&format!("File: {}\n{}\n", f.path, "-".repeat(20)));
-        out.push_str(&f.content);
-        out.push_str("\n\n");
-    }
+/// The JSON and YAML document, serialized straight to the output with files converted
+/// one at a time.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Document<'v, 'a> {
+    schema_version: u32,
+    generator: &'v GeneratorView,
+    header: Option<&'a str>,
+    prompt: Option<&'a str>,
+    intent: Option<&'a str>,
+    instructions: Option<&'v str>,
+    summary: &'v SummaryView<'a>,
+    changes: Option<&'v ChangesView<'a>>,
+    directory_structure: &'v [TreeEntry],
+    files: &'v FileList<'a>,
+    file_history: Option<&'v [&'a FileHistory]>,
+    git_diff: Option<&'v str>,
+    git_diff_files: Option<&'a [FileDiff]>,
+    git_log_commits: Option<&'a [Commit]>,
+}
 
-    if let Some(d) = diff {
-        out.push_str(&format!("GIT DIFF\n{}\n{}\n\n", "-".repeat(20), d));
+impl<'v, 'a> Document<'v, 'a> {
+    fn new(view: &'v PackView<'a>) -> Self {
+        Self {
+            schema_version: view.schema_version,
+            generator: &view.generator,
+            header: view.header,
+            prompt: view.prompt,
+            intent: view.intent,
+            instructions: view.instructions.as_deref(),
+            summary: &view.summary,
+            changes: view.changes.as_ref(),
+            directory_structure: &view.directory_structure,
+            files: &view.files,
+            file_history: view.file_history.as_deref(),
+            git_diff: view.git_diff.as_deref(),
+            git_diff_files: view.git_diff.is_some().then_some(view.git_diff_files),
+            git_log_commits: view.git_log.is_some().then_some(view.git_log_commits),
+        }
     }
+}
 
-    if let Some(l) = log {
-        out.push_str(&format!("GIT LOG\n{}\n{}\n\n", "-".repeat(20), l));
+/// File extension for outputs of the configured style, e.g. `xml` or `md`.
+/// Custom templates use their own inner extension (`pack.md.j2` -> `md`).
+pub fn file_extension(config: &RustymixConfig) -> String {
+    match config.output.style {
+        OutputStyle::Xml => "xml".to_string(),
+        OutputStyle::Markdown => "md".to_string(),
+        OutputStyle::Json => "json".to_string(),
+        OutputStyle::Plain => "txt".to_string(),
+        OutputStyle::Yaml => "yaml".to_string(),
+        OutputStyle::Html => "html".to_string(),
+        OutputStyle::Jsonl => "jsonl".to_string(),
+        OutputStyle::Template => config
+            .output
+            .template_path
+            .as_deref()
+            .and_then(|p| Path::new(p).file_stem())
+            .and_then(|stem| Path::new(stem).extension())
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_else(|| "txt".to_string()),
     }
-
-    out
 }
 
-fn generate_json(files: &[ProcessedFile], _config: &RustymixConfig, diff: Option<&str>, log: Option<&str>) -> String {
+fn write_jsonl(files: &[ProcessedFile], config: &RustymixConfig, out: &mut dyn Write) -> Result<()> {
     #[derive(Serialize)]
-    struct JsonOutput<'a> {
-        files: HashMap<&'a String, &'a String>,
-        git_diff: Option<&'a str>,
-        git_log: Option<&'a str>,
+    #[serde(rename_all = "camelCase")]
+    struct ChunkRecord<'a> {
+        path: &'a str,
+        symbol: Option<&'a str>,
+        kind: &'a str,
+        language: Option<&'static str>,
+        mode: &'static str,
+        start_byte: usize,
+        end_byte: usize,
+        start_line: usize,
+        end_line: usize,
+        tokens: usize,
+        content: &'a str,
     }
 
-    let mut file_map = HashMap::new();
     for f in files {
-        file_map.insert(&f.path, &f.content);
+        let ext = Path::new(&f.path).extension().and_then(|s| s.to_str()).unwrap_or("");
+        for chunk in language::chunking::chunk_content(&f.content, ext, config.output.chunk_max_tokens) {
+            let record = ChunkRecord {
+                path: &f.path,
+                symbol: chunk.symbol.as_deref(),
+                kind: &chunk.kind,
+                language: language::detect_language(&f.path),
+                mode: f.mode(),
+                start_byte: chunk.byte_range.start,
+                end_byte: chunk.byte_range.end,
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+                tokens: chunk.token_count,
+                content: &f.content[chunk.byte_range.clone()],
+            };
+            serde_json::to_writer(&mut *out, &record)?;
+            out.write_all(b"\n")?;
+        }
     }
-
-    let output = JsonOutput {
-        files: file_map,
-        git_diff: diff,
-        git_log: log,
-    };
-
-    serde_json::to_string_pretty(&output).unwrap_or_default()
+    Ok(())
 }
@@ -0,0 +1,93 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+
+use crate::cli::SurveyFormat;
+use crate::config::PromptConfig;
+use crate::output::ProcessedFile;
+use crate::review::ReviewSummary;
+use crate::survey;
+use crate::template;
+
+pub const SURVEY_PROMPT: &str = include_str!("templates/survey_prompt.j2");
+pub const SURVEY_JSON_PROMPT: &str = include_str!("templates/survey_json_prompt.j2");
+pub const BUILD_PROMPT: &str = include_str!("templates/build_prompt.j2");
+pub const REVIEW_PROMPT: &str = include_str!("templates/review_prompt.j2");
+
+/// Which instructions accompany the intent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptPhase {
+    /// Phase 1: skeleton pack, ask the model which files it needs.
+    Survey,
+    /// Phase 2: hybrid pack, ask the model to implement the intent.
+    Build,
+    /// `rustymix review`: changed files in full, ask the model to review the change set.
+    Review,
+}
+
+/// Placeholders available to prompt templates.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptVars<'a> {
+    pub intent: &'a str,
+    pub focus: Vec<&'a str>,
+    pub total_files: usize,
+    pub total_tokens: usize,
+    pub full_tokens: usize,
+    pub skeleton_tokens: usize,
+    /// The JSON Schema a `json` format survey reply has to follow.
+    pub reply_schema: &'static str,
+    /// The change set under review (review phase only).
+    pub review: Option<&'a ReviewSummary>,
+    /// Whether file headers carry a `mode` attribute (`--file-metadata` includes `mode`).
+    pub mode_marked: bool,
+}
+
+impl<'a> PromptVars<'a> {
+    pub fn new(intent: &'a str, focus: &'a [String], files: &[ProcessedFile]) -> Self {
+        let skeleton_tokens = files
+            .iter()
+            .filter(|f| f.is_skeleton)
+            .map(|f| f.token_count)
+            .sum();
+        let total_tokens = files.iter().map(|f| f.token_count).sum();
+        Self {
+            intent,
+            focus: focus.iter().map(String::as_str).collect(),
+            total_files: files.len(),
+            total_tokens,
+            full_tokens: total_tokens - skeleton_tokens,
+            skeleton_tokens,
+            reply_schema: survey::REPLY_SCHEMA,
+            review: None,
+            mode_marked: true,
+        }
+    }
+}
+
+/// Renders the instructions for `phase`, from the configured file or the built-in text.
+pub fn render(phase: PromptPhase, config: &PromptConfig, vars: &PromptVars) -> Result<String> {
+    let custom_path = match phase {
+        PromptPhase::Survey => config.survey_path.as_deref(),
+        PromptPhase::Build => config.build_path.as_deref(),
+        PromptPhase::Review => config.review_path.as_deref(),
+    };
+    let source = match custom_path {
+        Some(path) => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read prompt template {}", path))?,
+        None => match phase {
+            PromptPhase::Survey => match config.survey_format {
+                SurveyFormat::Cli => SURVEY_PROMPT.to_string(),
+                SurveyFormat::Json => SURVEY_JSON_PROMPT.to_string(),
+            },
+            PromptPhase::Build => BUILD_PROMPT.to_string(),
+            PromptPhase::Review => REVIEW_PROMPT.to_string(),
+        },
+    };
+
+    let mut prompt = template::render(&source, vars)?;
+    if !prompt.ends_with('\n') {
+        prompt.push('\n');
+    }
+    Ok(prompt)
+}
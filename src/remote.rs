@@ -0,0 +1,118 @@
+use anyhow::Result;
+use std::path::Path;
+
+/// What `--remote` (and `--remote-branch`) ask for: a repository, a revision in it and a
+/// directory to pack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteSpec {
+    /// The URL handed to `git`.
+    pub url: String,
+    /// Branch, tag or commit; the remote's HEAD when unset.
+    pub rev: Option<String>,
+    /// Directory inside the repository to check out and pack.
+    pub subdir: Option<String>,
+}
+
+/// Parses a `--remote` value:
+/// - a clone URL or a local path, packed at `branch` or the remote's HEAD;
+/// - `owner/repo`, short for the GitHub repository;
+/// - a forge URL (http, https or ssh) into a directory, `<url>/tree/<rev>/<subdir>`
+///   (GitHub, or `/-/tree/` on GitLab), or into a commit, `<url>/commit/<sha>`. Other
+///   URLs, such as `file://` ones, are passed on as they are.
+///
+/// In a `tree/` URL the revision ends at the first `/`; a branch containing `/` has to be
+/// given with `--remote-branch` as well, which then selects the split.
+pub fn parse(remote: &str, branch: Option<&str>) -> Result<RemoteSpec> {
+    let remote = remote.trim_end_matches('/');
+    let is_url = remote.contains("://") || remote.starts_with("git@");
+    if !is_url && !Path::new(remote).exists() && is_shorthand(remote) {
+        return Ok(RemoteSpec {
+            url: format!("https://github.com/{}.git", remote),
+            rev: branch.map(str::to_string),
+            subdir: None,
+        });
+    }
+    if !is_url {
+        return Ok(RemoteSpec {
+            url: remote.to_string(),
+            rev: branch.map(str::to_string),
+            subdir: None,
+        });
+    }
+
+    // Only the path after `<host>/<owner>/<repo>` can point into the repository
+    let marker = repo_end(remote).and_then(|start| {
+        ["/-/tree/", "/tree/", "/-/commit/", "/commit/"]
+            .into_iter()
+            .find_map(|m| {
+                let at = start + remote[start..].find(m)?;
+                Some((m, &remote[..at], &remote[at + m.len()..]))
+            })
+    });
+    let Some((marker, base, rest)) = marker else {
+        return Ok(RemoteSpec {
+            url: remote.to_string(),
+            rev: branch.map(str::to_string),
+            subdir: None,
+        });
+    };
+
+    let (rev, subdir) = if marker.ends_with("/commit/") {
+        (rest, "")
+    } else {
+        match branch {
+            Some(b) if rest == b || rest.starts_with(&format!("{}/", b)) => {
+                (b, rest[b.len()..].trim_start_matches('/'))
+            }
+            _ => rest.split_once('/').unwrap_or((rest, "")),
+        }
+    };
+    if let Some(b) = branch {
+        if b != rev {
+            anyhow::bail!(
+                "--remote-branch {} does not match the revision {} in {}",
+                b,
+                rev,
+                remote
+            );
+        }
+    }
+    if subdir.split('/').any(|part| part == "..") {
+        anyhow::bail!("Invalid directory in {}: {}", remote, subdir);
+    }
+    Ok(RemoteSpec {
+        url: base.to_string(),
+        rev: Some(rev.to_string()),
+        subdir: Some(subdir.to_string()).filter(|s| !s.is_empty()),
+    })
+}
+
+/// Byte offset of the `/` ending `<host>/<owner>/<repo>` (or `<host>:<owner>/<repo>`)
+/// in a forge URL, if anything follows the repository. Other URLs have no such layout.
+fn repo_end(url: &str) -> Option<usize> {
+    let (start, slashes) = match url.split_once("://") {
+        Some((scheme, _))
+            if ["http", "https", "ssh"].contains(&scheme.to_ascii_lowercase().as_str()) =>
+        {
+            (scheme.len() + 3, 3)
+        }
+        Some(_) => return None,
+        None => (url.find(':')? + 1, 2),
+    };
+    let (offset, _) = url[start..].match_indices('/').nth(slashes - 1)?;
+    Some(start + offset)
+}
+
+/// `owner/repo`, as GitHub names accept them.
+fn is_shorthand(remote: &str) -> bool {
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    match remote.split_once('/') {
+        Some((owner, repo)) => valid(owner) && valid(repo) && !owner.starts_with('.'),
+        None => false,
+    }
+}
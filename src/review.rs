@@ -0,0 +1,169 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::git::{DiffOptions, FileDiff, GitBackend};
+use crate::language::chunking;
+
+/// A `rustymix review <base>..<head>` run: the head revision, read from the object
+/// database like `--ref`, and what changed since the merge base.
+pub struct Review {
+    /// `base..head` as given on the command line (head defaults to HEAD).
+    pub range: String,
+    pub head: String,
+    /// The head as a full commit hash; the pack is a snapshot of it.
+    pub head_commit: String,
+    /// Merge base and head as full commit hashes, `merge_base..head_commit`.
+    pub commit_range: String,
+    /// The reviewed directory.
+    pub root: PathBuf,
+    /// Paths (relative to the reviewed directory) that exist at head and were changed.
+    pub changed: Vec<String>,
+}
+
+/// Resolves `range` (`base..head` or `base...head`, both compared from the merge base like
+/// a pull request) in `dir`, without touching its working tree.
+pub fn prepare(git: &dyn GitBackend, dir: &Path, range: &str) -> Result<Review> {
+    let (base, head) = range
+        .split_once("...")
+        .or_else(|| range.split_once(".."))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Review range must look like <base>..<head>, got {:?}",
+                range
+            )
+        })?;
+    if base.is_empty() {
+        anyhow::bail!("Review range {:?} has no base revision", range);
+    }
+    let head = if head.is_empty() { "HEAD" } else { head };
+
+    let head_commit = git.resolve_commit(dir, head)?;
+    git.resolve_commit(dir, base)?;
+    let merge_base = git.merge_base(dir, base, &head_commit)?;
+    let commit_range = format!("{}..{}", merge_base, head_commit);
+    let options = DiffOptions {
+        range: Some(commit_range.clone()),
+        ..Default::default()
+    };
+    let mut changed: Vec<String> = git
+        .diffs(dir, &options)?
+        .into_iter()
+        .filter(|d| d.status != "deleted")
+        .map(|d| d.path)
+        .collect();
+    changed.sort();
+    changed.dedup();
+
+    Ok(Review {
+        range: format!("{}..{}", base, head),
+        head: head.to_string(),
+        head_commit,
+        commit_range,
+        root: dir.to_path_buf(),
+        changed,
+    })
+}
+
+/// What the review prompt knows about the change set.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewSummary {
+    pub range: String,
+    pub head: String,
+    pub commits: usize,
+    pub files: Vec<TouchedFile>,
+}
+
+/// A changed file, the head-side lines the change touched and the definitions around them.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TouchedFile {
+    pub path: String,
+    pub status: &'static str,
+    /// Compact line ranges such as `3-5, 12`; empty for deleted files.
+    pub lines: String,
+    pub symbols: Vec<String>,
+}
+
+impl Review {
+    pub fn summarize(&self, git: &dyn GitBackend, diffs: &[FileDiff]) -> Result<ReviewSummary> {
+        let contents = self.head_contents(git)?;
+        let files = diffs
+            .iter()
+            .map(|d| {
+                let changed_lines = if d.status == "deleted" {
+                    Vec::new()
+                } else {
+                    d.changed_lines()
+                };
+                let extension = Path::new(&d.path)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("");
+                let symbols = contents
+                    .get(d.path.as_str())
+                    .map(|content| {
+                        chunking::enclosing_definitions(content, extension, &changed_lines)
+                    })
+                    .unwrap_or_default();
+                TouchedFile {
+                    path: d.path.clone(),
+                    status: d.status,
+                    lines: line_ranges(&changed_lines),
+                    symbols,
+                }
+            })
+            .collect();
+        Ok(ReviewSummary {
+            range: self.range.clone(),
+            head: self.head.clone(),
+            commits: git.count_commits(&self.root, &self.commit_range)?,
+            files,
+        })
+    }
+
+    /// The changed files as of the head commit.
+    fn head_contents(&self, git: &dyn GitBackend) -> Result<HashMap<&str, String>> {
+        let entries = git.ls_tree(&self.root, &self.head_commit)?;
+        let blob_ids: HashMap<&str, &str> = entries
+            .iter()
+            .filter(|e| !e.is_dir)
+            .map(|e| (e.path.as_str(), e.object.as_str()))
+            .collect();
+        let wanted: Vec<(&str, &str)> = self
+            .changed
+            .iter()
+            .filter_map(|path| Some((path.as_str(), *blob_ids.get(path.as_str())?)))
+            .collect();
+        let objects: Vec<&str> = wanted.iter().map(|(_, object)| *object).collect();
+        let blobs = git.read_blobs(&self.root, &objects)?;
+        Ok(wanted
+            .iter()
+            .zip(blobs)
+            .map(|((path, _), blob)| (*path, String::from_utf8_lossy(&blob).to_string()))
+            .collect())
+    }
+}
+
+fn line_ranges(lines: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &line in lines {
+        match ranges.last_mut() {
+            Some((_, end)) if line <= *end + 1 => *end = (*end).max(line),
+            _ => ranges.push((line, line)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use globset::GlobSet;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::Override;
+use ignore::Match;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::git::{GitBackend, TreeEntry};
+use crate::state;
+
+/// A directory as of a git revision, read from the object database instead of the
+/// working tree.
+pub struct Snapshot {
+    /// Absolute paths (as if checked out under the root) with the blob contents.
+    pub files: HashMap<PathBuf, Vec<u8>>,
+    /// Directories that survived the ignore rules, relative to the root.
+    pub dirs: Vec<String>,
+}
+
+/// Lists `rev` below `root` and reads its files, dropping what a filesystem walk would
+/// skip: hidden entries, the state directory, `overrides` (default, `--ignore` and
+/// `--include` patterns), `custom_ignore` and, when enabled, the `.gitignore` and
+/// `.rustymixignore` files committed in the tree.
+pub fn load(
+    git: &dyn GitBackend,
+    root: &Path,
+    rev: &str,
+    overrides: &Override,
+    custom_ignore: &GlobSet,
+    use_gitignore: bool,
+    use_ignore_files: bool,
+) -> Result<Snapshot> {
+    let entries = git
+        .ls_tree(root, rev)
+        .with_context(|| format!("Cannot list revision {:?}", rev))?;
+
+    let mut ignore_file_names = Vec::new();
+    if use_gitignore {
+        ignore_file_names.push(".gitignore");
+    }
+    if use_ignore_files {
+        ignore_file_names.push(".rustymixignore");
+    }
+    let matchers = ignore_matchers(git, root, &entries, &ignore_file_names)?;
+
+    let mut kept_files: Vec<&TreeEntry> = Vec::new();
+    let mut dirs = Vec::new();
+    // Entries come parent first, so an excluded directory is known before its contents
+    let mut excluded_dirs: Vec<&str> = Vec::new();
+    for entry in &entries {
+        let inside_excluded = excluded_dirs.iter().any(|d| {
+            entry
+                .path
+                .strip_prefix(d)
+                .is_some_and(|r| r.starts_with('/'))
+        });
+        if inside_excluded {
+            continue;
+        }
+        let name = entry.path.rsplit('/').next().unwrap_or(&entry.path);
+        let abs = root.join(&entry.path);
+        let excluded = name.starts_with('.')
+            || name == state::STATE_DIR_NAME
+            || overrides.matched(&abs, entry.is_dir).is_ignore()
+            || custom_ignore.is_match(&entry.path)
+            || is_ignored(&matchers, &entry.path, entry.is_dir);
+        if entry.is_dir {
+            if excluded {
+                excluded_dirs.push(&entry.path);
+            } else {
+                dirs.push(entry.path.clone());
+            }
+        } else if !excluded {
+            kept_files.push(entry);
+        }
+    }
+
+    let objects: Vec<&str> = kept_files.iter().map(|e| e.object.as_str()).collect();
+    let blobs = git.read_blobs(root, &objects)?;
+    let files = kept_files
+        .iter()
+        .map(|e| root.join(&e.path))
+        .zip(blobs)
+        .collect();
+    Ok(Snapshot { files, dirs })
+}
+
+/// One matcher per directory holding ignore files, keyed by the directory's relative
+/// path (`""` for the root).
+fn ignore_matchers(
+    git: &dyn GitBackend,
+    root: &Path,
+    entries: &[TreeEntry],
+    file_names: &[&str],
+) -> Result<BTreeMap<String, Gitignore>> {
+    let ignore_files: Vec<&TreeEntry> = entries
+        .iter()
+        .filter(|e| {
+            !e.is_dir
+                && file_names
+                    .iter()
+                    .any(|n| e.path.rsplit('/').next() == Some(n))
+        })
+        .collect();
+    let objects: Vec<&str> = ignore_files.iter().map(|e| e.object.as_str()).collect();
+    let contents = git.read_blobs(root, &objects)?;
+
+    let mut builders: BTreeMap<String, GitignoreBuilder> = BTreeMap::new();
+    for (entry, content) in ignore_files.iter().zip(contents) {
+        let dir = entry
+            .path
+            .rsplit_once('/')
+            .map_or("", |(d, _)| d)
+            .to_string();
+        let builder = builders
+            .entry(dir.clone())
+            .or_insert_with(|| GitignoreBuilder::new(root.join(&dir)));
+        for line in String::from_utf8_lossy(&content).lines() {
+            builder.add_line(None, line)?;
+        }
+    }
+    builders
+        .into_iter()
+        .map(|(dir, builder)| Ok((dir, builder.build()?)))
+        .collect()
+}
+
+/// Whether the closest ignore file with an opinion on `path` ignores it.
+fn is_ignored(matchers: &BTreeMap<String, Gitignore>, path: &str, is_dir: bool) -> bool {
+    // Deepest directory first: nested ignore files override their parents
+    for (dir, matcher) in matchers.iter().rev() {
+        let relative = if dir.is_empty() {
+            Some(path)
+        } else {
+            path.strip_prefix(dir.as_str())
+                .and_then(|r| r.strip_prefix('/'))
+        };
+        let Some(relative) = relative else {
+            continue;
+        };
+        match matcher.matched(relative, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    false
+}
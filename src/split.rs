@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+
+use crate::fs_tools;
+use crate::output::ProcessedFile;
+
+/// Rough token cost of the per-file wrapper (path attribute, separators) in every style;
+/// only a first estimate, as rendered parts are measured against the budget afterwards.
+const FILE_OVERHEAD_TOKENS: usize = 16;
+
+/// One self-contained slice of a split pack.
+#[derive(Debug, Default)]
+pub struct Part {
+    pub files: Vec<ProcessedFile>,
+    /// Human readable notes for files that were split across parts, e.g.
+    /// "src/big.rs (lines 1-120 of 400)".
+    pub continued: Vec<String>,
+}
+
+/// Groups files into parts whose file content stays within `budget` tokens, preserving
+/// order. Files are kept whole where possible; a file larger than the budget is split at
+/// line boundaries (or within a line too long on its own) over as many parts as it needs.
+pub fn split_files(files: &[ProcessedFile], budget: usize) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut current = Part::default();
+    let mut used = 0;
+
+    for f in files {
+        let cost = file_cost(f);
+        if cost > budget {
+            for (piece, note) in split_file(f, budget) {
+                let piece_cost = file_cost(&piece);
+                if used > 0 && used + piece_cost > budget {
+                    parts.push(std::mem::take(&mut current));
+                    used = 0;
+                }
+                used += piece_cost;
+                current.files.push(piece);
+                current.continued.push(note);
+            }
+            continue;
+        }
+
+        if used > 0 && used + cost > budget {
+            parts.push(std::mem::take(&mut current));
+            used = 0;
+        }
+        used += cost;
+        current.files.push(f.clone());
+    }
+
+    if !current.files.is_empty() || parts.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+fn file_cost(f: &ProcessedFile) -> usize {
+    f.token_count + fs_tools::count_tokens(&f.path) + FILE_OVERHEAD_TOKENS
+}
+
+/// Splits one oversized file into line-aligned pieces that each fit in `budget`. A line
+/// too long on its own (minified code, data) is cut by tokens.
+fn split_file(f: &ProcessedFile, budget: usize) -> Vec<(ProcessedFile, String)> {
+    let available = budget
+        .saturating_sub(fs_tools::count_tokens(&f.path) + FILE_OVERHEAD_TOKENS)
+        .max(1);
+    let lines: Vec<&str> = f.content.lines().collect();
+    let line_range = |range: std::ops::Range<usize>| {
+        let note = format!(
+            "{} (lines {}-{} of {})",
+            f.path,
+            range.start + 1,
+            range.end,
+            lines.len()
+        );
+        (lines[range].join("\n"), note)
+    };
+
+    // Content and note of every piece
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let line_tokens = fs_tools::count_tokens(line) + 1;
+        if line_tokens > available {
+            if i > start {
+                pieces.push(line_range(start..i));
+            }
+            let mut cut_start = 0;
+            for cut in fs_tools::token_cuts(line, available) {
+                let note = format!("{} (part of line {} of {})", f.path, i + 1, lines.len());
+                pieces.push((line[cut_start..cut].to_string(), note));
+                cut_start = cut;
+            }
+            start = i + 1;
+            tokens = 0;
+            continue;
+        }
+        if i > start && tokens + line_tokens > available {
+            pieces.push(line_range(start..i));
+            start = i;
+            tokens = 0;
+        }
+        tokens += line_tokens;
+    }
+    if start < lines.len() || pieces.is_empty() {
+        pieces.push(line_range(start..lines.len()));
+    }
+    let truncated = pieces.len() > 1;
+
+    pieces
+        .into_iter()
+        .map(|(content, note)| {
+            let piece = ProcessedFile {
+                path: f.path.clone(),
+                char_count: content.chars().count(),
+                token_count: fs_tools::count_tokens(&content),
+                content,
+                original_line_count: f.original_line_count,
+                byte_count: f.byte_count,
+                is_skeleton: f.is_skeleton,
+                truncated,
+            };
+            (piece, note)
+        })
+        .collect()
+}
+
+/// Instructions placed at the top of every part so the model waits for the full pack.
+pub fn part_header(index: usize, total: usize, continued: &[String]) -> String {
+    let mut header = if index + 1 < total {
+        format!(
+            "This is part {} of {} of a split context pack. \
+             Do not answer yet: wait until you have received all {} parts, \
+             then follow the instructions in the final part.\n",
+            index + 1,
+            total,
+            total
+        )
+    } else {
+        format!(
+            "This is part {} of {}, the final part of a split context pack. \
+             All parts have now been provided; read them together and follow the instructions below.\n",
+            index + 1,
+            total
+        )
+    };
+    if !continued.is_empty() {
+        header.push_str("Files split across parts (this part contains):\n");
+        for note in continued {
+            header.push_str(&format!("- {}\n", note));
+        }
+    }
+    header
+}
+
+/// `rustymix-output.xml` -> `rustymix-output.2.xml`
+pub fn part_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, index + 1, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, index + 1),
+    };
+    path.with_file_name(name)
+}
@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::fs_tools;
+use crate::output::ProcessedFile;
+
+/// Name of the per-repository directory holding rustymix state between runs.
+pub const STATE_DIR_NAME: &str = ".rustymix";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub path: String,
+    pub hash: String,
+    pub mode: String,
+    pub token_count: usize,
+    pub char_count: usize,
+}
+
+/// Record of what a single pack contained, used to compute deltas on the next run.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PackManifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeSummary {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+    pub previous_tokens: usize,
+    pub current_tokens: usize,
+}
+
+impl PackManifest {
+    pub fn from_files(files: &[ProcessedFile]) -> Self {
+        let files = files
+            .iter()
+            .map(|f| ManifestEntry {
+                path: f.path.clone(),
+                hash: fs_tools::content_hash(&f.content),
+                mode: f.mode().to_string(),
+                token_count: f.token_count,
+                char_count: f.char_count,
+            })
+            .collect();
+        Self { files }
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.files.iter().map(|f| f.token_count).sum()
+    }
+
+    /// Compares this (current) manifest against the previous one.
+    pub fn diff(&self, previous: &PackManifest) -> ChangeSummary {
+        let before: BTreeMap<&str, &ManifestEntry> = previous
+            .files
+            .iter()
+            .map(|e| (e.path.as_str(), e))
+            .collect();
+        let after: BTreeMap<&str, &ManifestEntry> =
+            self.files.iter().map(|e| (e.path.as_str(), e)).collect();
+
+        let mut summary = ChangeSummary {
+            previous_tokens: previous.total_tokens(),
+            current_tokens: self.total_tokens(),
+            ..Default::default()
+        };
+
+        for (path, entry) in &after {
+            match before.get(path) {
+                None => summary.added.push(path.to_string()),
+                Some(old) if old.hash != entry.hash || old.mode != entry.mode => {
+                    summary.changed.push(path.to_string())
+                }
+                Some(_) => summary.unchanged += 1,
+            }
+        }
+        for path in before.keys() {
+            if !after.contains_key(path) {
+                summary.removed.push(path.to_string());
+            }
+        }
+
+        summary
+    }
+}
+
+impl ChangeSummary {
+    pub fn includes(&self, path: &str) -> bool {
+        self.added.iter().any(|p| p == path) || self.changed.iter().any(|p| p == path)
+    }
+
+    /// One-line human readable description of the delta.
+    pub fn headline(&self) -> String {
+        format!(
+            "{} added, {} changed, {} removed, {} unchanged (tokens: {} -> {})",
+            self.added.len(),
+            self.changed.len(),
+            self.removed.len(),
+            self.unchanged,
+            self.previous_tokens,
+            self.current_tokens
+        )
+    }
+}
+
+pub fn state_dir(root: &Path) -> PathBuf {
+    root.join(STATE_DIR_NAME)
+}
+
+fn manifest_path(root: &Path, session: &str) -> PathBuf {
+    state_dir(root).join(format!("manifest-{}.json", session))
+}
+
+/// Loads the manifest written by the previous pack of `session`, if any.
+pub fn load_manifest(root: &Path, session: &str) -> Result<Option<PackManifest>> {
+    let path = manifest_path(root, session);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+    let manifest = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse manifest {}", path.display()))?;
+    Ok(Some(manifest))
+}
+
+pub fn save_manifest(root: &Path, session: &str, manifest: &PackManifest) -> Result<()> {
+    let dir = state_dir(root);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create state directory {}", dir.display()))?;
+
+    // Keep the state directory out of `git status` without touching the user's .gitignore.
+    let gitignore = dir.join(".gitignore");
+    if !gitignore.exists() {
+        fs::write(&gitignore, "*\n")?;
+    }
+
+    let path = manifest_path(root, session);
+    fs::write(&path, serde_json::to_string_pretty(manifest)?)
+        .with_context(|| format!("Failed to write manifest {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use globset::Glob;
+use serde::Deserialize;
+use std::fs;
+use std::io::Read;
+
+use crate::output::ProcessedFile;
+
+/// JSON Schema for the reply the JSON survey prompt asks the model for. It is embedded in
+/// the prompt verbatim, and [`parse_reply`] enforces the same rules.
+pub const REPLY_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "RustymixSurveyReply",
+  "type": "object",
+  "additionalProperties": false,
+  "required": ["focus", "rationale"],
+  "properties": {
+    "focus": {
+      "description": "Glob patterns, relative to the repository root, of files to read in FULL TEXT.",
+      "type": "array",
+      "minItems": 1,
+      "items": { "type": "string", "minLength": 1 }
+    },
+    "ignore": {
+      "description": "Glob patterns of files to EXCLUDE from the next pack entirely.",
+      "type": "array",
+      "items": { "type": "string", "minLength": 1 }
+    },
+    "symbols": {
+      "description": "Functions, types or modules most relevant to the request.",
+      "type": "array",
+      "items": { "type": "string", "minLength": 1 }
+    },
+    "rationale": {
+      "description": "One or two sentences explaining the selection.",
+      "type": "string"
+    }
+  }
+}"#;
+
+/// A model's answer to the JSON survey prompt.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SurveyReply {
+    pub focus: Vec<String>,
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    pub rationale: String,
+}
+
+/// Reads a reply from a file, or from stdin when `path` is `-`.
+pub fn load_reply(path: &str) -> Result<SurveyReply> {
+    let raw = if path == "-" {
+        let mut buf = String::new();
+        std::io::stdin().read_to_string(&mut buf)?;
+        buf
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Failed to read survey reply {}", path))?
+    };
+    parse_reply(&raw)
+}
+
+/// Parses and schema-checks a reply. Models often wrap JSON in a markdown fence, so the
+/// first `{ ... }` object in the text is used.
+pub fn parse_reply(raw: &str) -> Result<SurveyReply> {
+    let json = match (raw.find('{'), raw.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &raw[start..=end],
+        _ => anyhow::bail!("Survey reply does not contain a JSON object"),
+    };
+    let reply: SurveyReply =
+        serde_json::from_str(json).context("Survey reply does not match the reply schema")?;
+
+    let mut problems = Vec::new();
+    if reply.focus.is_empty() {
+        problems.push("`focus` must list at least one pattern".to_string());
+    }
+    for (field, patterns) in [("focus", &reply.focus), ("ignore", &reply.ignore)] {
+        for pattern in patterns {
+            if pattern.trim().is_empty() {
+                problems.push(format!("`{}` contains an empty pattern", field));
+            } else if let Err(e) = Glob::new(pattern.trim()) {
+                problems.push(format!(
+                    "`{}` pattern {:?} is not a valid glob: {}",
+                    field, pattern, e
+                ));
+            }
+        }
+    }
+    if reply.symbols.iter().any(|s| s.trim().is_empty()) {
+        problems.push("`symbols` contains an empty name".to_string());
+    }
+    report(problems)?;
+    Ok(reply)
+}
+
+/// Checks the reply against the files the walk actually found (after ignore patterns,
+/// including the reply's own): every focus pattern has to select at least one of them.
+pub fn validate_against_files(reply: &SurveyReply, rel_paths: &[String]) -> Result<()> {
+    let mut problems = Vec::new();
+    for pattern in &reply.focus {
+        let matcher = Glob::new(pattern.trim())?.compile_matcher();
+        if !rel_paths.iter().any(|p| matcher.is_match(p)) {
+            problems.push(format!(
+                "focus pattern {:?} matches no packed file",
+                pattern
+            ));
+        }
+    }
+    report(problems)
+}
+
+/// Symbols that do not occur in any full-text file of the pack.
+pub fn missing_symbols<'a>(reply: &'a SurveyReply, files: &[ProcessedFile]) -> Vec<&'a str> {
+    reply
+        .symbols
+        .iter()
+        .map(|s| s.trim())
+        .filter(|s| {
+            !files
+                .iter()
+                .any(|f| !f.is_skeleton && f.content.contains(s))
+        })
+        .collect()
+}
+
+fn report(problems: Vec<String>) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    anyhow::bail!("Invalid survey reply:\n  - {}", problems.join("\n  - "))
+}
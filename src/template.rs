@@ -0,0 +1,489 @@
+use anyhow::{Context, Result};
+use minijinja::value::Object;
+use minijinja::{AutoEscape, Environment, ErrorKind, Value};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::cli::FileMetadata;
+use crate::config::RustymixConfig;
+use crate::fs_tools;
+use crate::git::{Commit, FileDiff, FileHistory, LastCommit};
+use crate::language;
+use crate::output::{top_files, PackContext, ProcessedFile, TopFile, JSON_SCHEMA_VERSION};
+use crate::state::ChangeSummary;
+use crate::tree::TreeEntry;
+
+pub const XML_TEMPLATE: &str = include_str!("templates/xml.j2");
+pub const MARKDOWN_TEMPLATE: &str = include_str!("templates/markdown.j2");
+pub const PLAIN_TEMPLATE: &str = include_str!("templates/plain.j2");
+pub const HTML_TEMPLATE: &str = include_str!("templates/html.j2");
+
+/// Minimum length of fences and separators, matching the classic ``` and 20-dash rule.
+const FENCE_LEN: usize = 3;
+const PLAIN_SEPARATOR_LEN: usize = 20;
+
+/// Everything a template can see. Field names are camelCase, matching the JSON style,
+/// e.g. `{{ files[0].tokens }}` or `{% for f in files %}`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackView<'a> {
+    pub schema_version: u32,
+    pub generator: GeneratorView,
+    pub header: Option<&'a str>,
+    /// The generated survey, build or review instructions for the intent.
+    pub prompt: Option<&'a str>,
+    pub intent: Option<&'a str>,
+    pub instructions: Option<String>,
+    pub summary: SummaryView<'a>,
+    pub changes: Option<ChangesView<'a>>,
+    pub directory_structure: Vec<TreeEntry>,
+    /// The indented text rendering of `directoryStructure`.
+    pub directory_tree: String,
+    pub files: FileList<'a>,
+    /// Tokens of the largest packed file, e.g. to scale per-file bars.
+    pub largest_file_tokens: usize,
+    /// Recent commits and recently changed line ranges of the files in this pack.
+    pub file_history: Option<Vec<&'a FileHistory>>,
+    /// The whole diff as one text, as `git diff` prints it.
+    pub git_diff: Option<String>,
+    /// The same diff split per file, each marked with whether the file is in the pack.
+    pub git_diff_files: &'a [FileDiff],
+    /// The log as one text, in `git log --name-only` layout.
+    pub git_log: Option<String>,
+    /// The same commits as records (`hash`, `author`, `email`, `date`, `subject`, `body`,
+    /// `files` and, with `--log-diffs`, `patch`).
+    pub git_log_commits: &'a [Commit],
+    /// A `-` rule longer than any run of dashes in the packed files, so file separators in
+    /// the plain style cannot be confused with file content.
+    pub separator: String,
+    /// Whether the XML style wraps text in CDATA sections instead of escaping it.
+    pub xml_cdata: bool,
+}
+
+#[derive(Serialize)]
+pub struct GeneratorView {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SummaryView<'a> {
+    pub description: &'static str,
+    pub total_files: usize,
+    pub total_tokens: usize,
+    pub total_chars: usize,
+    pub top_files_length: usize,
+    pub top_files_by_tokens: Vec<TopFile<'a>>,
+    pub top_files_by_chars: Vec<TopFile<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct ChangesView<'a> {
+    #[serde(flatten)]
+    pub summary: &'a ChangeSummary,
+    pub headline: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileView<'a> {
+    pub path: &'a str,
+    pub language: Option<&'static str>,
+    pub mode: &'static str,
+    pub tokens: usize,
+    pub chars: usize,
+    pub hash: String,
+    pub original_line_count: usize,
+    /// The fields selected with `--file-metadata`, for file headers.
+    pub metadata: FileMetadataView<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<&'a str>,
+}
+
+impl<'a> FileView<'a> {
+    fn new(
+        f: &'a ProcessedFile,
+        fields: &[FileMetadata],
+        last_commits: &'a HashMap<String, LastCommit>,
+    ) -> Self {
+        Self {
+            path: &f.path,
+            language: language::detect_language(&f.path),
+            mode: f.mode(),
+            tokens: f.token_count,
+            chars: f.char_count,
+            hash: fs_tools::content_hash(&f.content),
+            original_line_count: f.original_line_count,
+            metadata: FileMetadataView::new(f, fields, last_commits),
+            content: Some(&f.content),
+        }
+    }
+}
+
+/// The packed files, turned into [`FileView`]s one at a time as they are serialized.
+pub struct FileList<'a> {
+    files: &'a [ProcessedFile],
+    fields: &'a [FileMetadata],
+    last_commits: &'a HashMap<String, LastCommit>,
+    /// Whether the views carry the file contents or only their metadata.
+    contents: bool,
+}
+
+impl Serialize for FileList<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.files.iter().map(|f| {
+            let mut view = FileView::new(f, self.fields, self.last_commits);
+            if !self.contents {
+                view.content = None;
+            }
+            view
+        }))
+    }
+}
+
+/// Selected per-file metadata; unselected (or unknown) fields are left out entirely.
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FileMetadataView<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_commit: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_commit_date: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_commit_author: Option<&'a str>,
+}
+
+impl<'a> FileMetadataView<'a> {
+    fn new(
+        f: &ProcessedFile,
+        fields: &[FileMetadata],
+        last_commits: &'a HashMap<String, LastCommit>,
+    ) -> Self {
+        let mut view = Self::default();
+        for field in fields {
+            match field {
+                FileMetadata::Mode => view.mode = Some(f.mode()),
+                FileMetadata::Language => view.language = language::detect_language(&f.path),
+                FileMetadata::Lines => view.lines = Some(f.original_line_count),
+                FileMetadata::Tokens => view.tokens = Some(f.token_count),
+                FileMetadata::Bytes => view.bytes = Some(f.byte_count),
+                FileMetadata::Truncated => view.truncated = Some(f.truncated),
+                FileMetadata::Commit => {
+                    if let Some(commit) = last_commits.get(&f.path) {
+                        view.last_commit = Some(&commit.hash);
+                        view.last_commit_date = Some(&commit.date);
+                        view.last_commit_author = Some(&commit.author);
+                    }
+                }
+            }
+        }
+        view
+    }
+}
+
+impl<'a> PackView<'a> {
+    pub fn new(
+        files: &'a [ProcessedFile],
+        config: &'a RustymixConfig,
+        ctx: &PackContext<'a>,
+    ) -> Self {
+        let top_n = config.output.top_files_length;
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            generator: GeneratorView {
+                name: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+            },
+            header: config.output.header_text.as_deref(),
+            prompt: ctx.prompt,
+            intent: ctx.intent,
+            instructions: config
+                .output
+                .instruction_file_path
+                .as_ref()
+                .and_then(|p| fs::read_to_string(p).ok()),
+            summary: SummaryView {
+                description: "This file is a merged representation of the codebase.",
+                total_files: files.len(),
+                total_tokens: files.iter().map(|f| f.token_count).sum(),
+                total_chars: files.iter().map(|f| f.char_count).sum(),
+                top_files_length: top_n,
+                top_files_by_tokens: top_files(files, top_n, |f| f.token_count),
+                top_files_by_chars: top_files(files, top_n, |f| f.char_count),
+            },
+            changes: ctx.changes.map(|c| ChangesView {
+                summary: c,
+                headline: c.headline(),
+            }),
+            directory_structure: ctx.tree.entries(),
+            directory_tree: ctx.tree.render(config.output.directory_tree_metrics),
+            files: FileList {
+                files,
+                fields: &config.output.file_metadata,
+                last_commits: ctx.last_commits,
+                contents: true,
+            },
+            largest_file_tokens: files.iter().map(|f| f.token_count).max().unwrap_or(0),
+            file_history: ctx.file_history.map(|histories| {
+                histories
+                    .iter()
+                    .filter(|h| files.iter().any(|f| f.path == h.path))
+                    .collect()
+            }),
+            git_diff: ctx
+                .git_diff
+                .map(|diffs| diffs.iter().map(|d| d.patch.as_str()).collect()),
+            git_diff_files: ctx.git_diff.unwrap_or_default(),
+            git_log: ctx.git_log.map(log_text),
+            git_log_commits: ctx.git_log.unwrap_or_default(),
+            separator: "-".repeat(
+                files
+                    .iter()
+                    .map(|f| longest_run(&f.content, '-') + 1)
+                    .fold(PLAIN_SEPARATOR_LEN, usize::max),
+            ),
+            xml_cdata: config.output.xml_cdata,
+        }
+    }
+}
+
+/// The commits in `git log --name-only` layout, each followed by its patch if any.
+fn log_text(commits: &[Commit]) -> String {
+    let mut out = String::new();
+    for c in commits {
+        out.push_str(&format!(
+            "commit {}\nAuthor: {} <{}>\nDate:   {}\n\n",
+            c.hash, c.author, c.email, c.date
+        ));
+        let message = if c.body.is_empty() {
+            c.subject.clone()
+        } else {
+            format!("{}\n\n{}", c.subject, c.body)
+        };
+        for line in message.lines() {
+            if line.is_empty() {
+                out.push('\n');
+            } else {
+                out.push_str(&format!("    {}\n", line));
+            }
+        }
+        if !c.files.is_empty() {
+            out.push('\n');
+            for file in &c.files {
+                out.push_str(&format!("{}\n", file));
+            }
+        }
+        if let Some(patch) = &c.patch {
+            out.push('\n');
+            out.push_str(patch);
+        }
+        out.push('\n');
+    }
+    out.trim_end().to_string()
+}
+
+/// Renders `source` against `view` into a string.
+pub fn render<S: Serialize>(source: &str, view: &S) -> Result<String> {
+    let env = environment();
+    let template = env
+        .template_from_str(source)
+        .context("Failed to parse template")?;
+    template.render(view).context("Failed to render template")
+}
+
+/// Renders a pack with `source` into `out`.
+///
+/// Templates that define `header`, `file` and `footer` blocks (all built-in ones do) are
+/// rendered piece by piece: the header, then the `file` block once per file, then the
+/// footer, each written to `out` as soon as it is rendered. Only the file being rendered
+/// is converted for the template, as `file`; `files` lists the files without `content`.
+/// Other templates are rendered in one go against the whole view.
+pub fn render_pack(source: &str, mut view: PackView, out: &mut dyn Write) -> Result<()> {
+    let mut env = environment();
+    view.files.contents = false;
+    let globals = Value::from_serialize(&view);
+    for key in globals.try_iter().map_err(render_error)? {
+        let value = globals.get_item(&key).map_err(render_error)?;
+        env.add_global(key.to_string(), value);
+    }
+    let current = Arc::new(CurrentFile::default());
+    env.add_global("file", Value::from_dyn_object(current.clone()));
+
+    let template = env
+        .template_from_str(source)
+        .context("Failed to parse output template")?;
+    let mut state = template.new_state();
+    match state.render_block_to_write("header", &mut *out) {
+        Err(e) if e.kind() == ErrorKind::UnknownBlock => {
+            view.files.contents = true;
+            return template
+                .render_captured_to(&view, out)
+                .map(|_| ())
+                .map_err(render_error);
+        }
+        result => result.map_err(render_error)?,
+    }
+    let FileList {
+        files,
+        fields,
+        last_commits,
+        ..
+    } = view.files;
+    for f in files {
+        *current.0.lock().unwrap() = Value::from_serialize(FileView::new(f, fields, last_commits));
+        state
+            .render_block_to_write("file", &mut *out)
+            .map_err(render_error)?;
+    }
+    state
+        .render_block_to_write("footer", &mut *out)
+        .map_err(render_error)
+}
+
+/// The file a pack template is rendering, swapped out before each `file` block.
+#[derive(Debug, Default)]
+struct CurrentFile(Mutex<Value>);
+
+impl Object for CurrentFile {
+    fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+        let file = self.0.lock().unwrap();
+        file.get_item(key).ok().filter(|v| !v.is_undefined())
+    }
+}
+
+fn render_error(e: minijinja::Error) -> anyhow::Error {
+    anyhow::Error::new(e).context("Failed to render output template")
+}
+
+/// Built-in and user templates share the same environment: block tags swallow their own
+/// line, and nothing is auto-escaped (use the `xml_*` filters where needed).
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_auto_escape_callback(|_| AutoEscape::None);
+    env.add_filter("xml_escape", xml_escape);
+    env.add_filter("xml_attr", xml_attr);
+    env.add_filter("xml_text", xml_text);
+    env.add_filter("fence", fence);
+    env.add_filter("extension", extension);
+    env.add_filter("percent", percent);
+    env.add_filter("json_pretty", json_pretty);
+    env.add_filter("yaml", yaml);
+    env.add_filter("html_escape", html_escape);
+    env.add_filter("highlight", highlight);
+    env
+}
+
+/// Reads a user supplied template file.
+pub fn load(path: &str) -> Result<String> {
+    fs::read_to_string(path).with_context(|| format!("Failed to read template {}", path))
+}
+
+/// Escapes text content. Characters XML 1.0 cannot represent at all (most control
+/// characters) are replaced with U+FFFD, since no escape makes them valid.
+fn xml_escape(value: String) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(xml_char(c)),
+        }
+    }
+    out
+}
+
+/// Escapes a value for use inside a double-quoted attribute.
+fn xml_attr(value: String) -> String {
+    xml_escape(value)
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Text content, either escaped or (with `cdata` set) wrapped in a CDATA section. A `]]>`
+/// inside the value would end the section early, so it is split across two sections.
+fn xml_text(value: String, cdata: Option<bool>) -> String {
+    if !cdata.unwrap_or(false) {
+        return xml_escape(value);
+    }
+    let value: String = value.chars().map(xml_char).collect();
+    format!("<![CDATA[{}]]>", value.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+fn xml_char(c: char) -> char {
+    match c {
+        '\t' | '\n' | '\r' => c,
+        '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => '\u{fffd}',
+        c => c,
+    }
+}
+
+/// A backtick fence longer than any backtick run in `value`, so the fenced block cannot
+/// be closed early by the content itself.
+fn fence(value: String) -> String {
+    "`".repeat((longest_run(&value, '`') + 1).max(FENCE_LEN))
+}
+
+fn longest_run(text: &str, ch: char) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in text.chars() {
+        if c == ch {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    longest
+}
+
+fn extension(path: String) -> String {
+    Path::new(&path)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+fn percent(share: f64) -> String {
+    format!("{:.1}%", share)
+}
+
+fn json_pretty(value: Value) -> Result<String, minijinja::Error> {
+    serde_json::to_string_pretty(&value)
+        .map_err(|e| minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, e.to_string()))
+}
+
+fn yaml(value: Value) -> Result<String, minijinja::Error> {
+    serde_norway::to_string(&value)
+        .map_err(|e| minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, e.to_string()))
+}
+
+fn html_escape(value: String) -> String {
+    language::highlight::escape_html(&value)
+}
+
+/// HTML-escaped source with syntax spans, e.g. `{{ f.content|highlight(f.language) }}`.
+fn highlight(value: String, language: Option<String>) -> String {
+    language::highlight::highlight_html(&value, language.as_deref())
+}
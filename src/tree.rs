@@ -0,0 +1,130 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::output::ProcessedFile;
+
+/// Directory tree of the packed files, rendered into the `directory_structure` section.
+#[derive(Debug, Default, Clone)]
+pub struct DirectoryTree {
+    root: TreeNode,
+}
+
+/// Serializable form of the tree, used by structured output styles.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_count: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreeEntry>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct TreeNode {
+    dirs: BTreeMap<String, TreeNode>,
+    files: BTreeSet<String>,
+    file_count: usize,
+    token_count: usize,
+}
+
+impl DirectoryTree {
+    /// Builds the tree from the packed files. `extra_dirs` are directories seen during the
+    /// walk that should be listed even when none of their files made it into the pack.
+    pub fn build(files: &[ProcessedFile], extra_dirs: &[String]) -> Self {
+        let mut tree = Self::default();
+        for dir in extra_dirs {
+            tree.root.dir_mut(&components(dir));
+        }
+        for f in files {
+            let mut parts = components(&f.path);
+            let Some(name) = parts.pop() else {
+                continue;
+            };
+
+            let mut node = &mut tree.root;
+            node.file_count += 1;
+            node.token_count += f.token_count;
+            for part in parts {
+                node = node.dirs.entry(part.to_string()).or_default();
+                node.file_count += 1;
+                node.token_count += f.token_count;
+            }
+            node.files.insert(name.to_string());
+        }
+        tree
+    }
+
+    /// Renders an indented tree, directories first. With `show_metrics`, each directory
+    /// is annotated with the number of packed files and tokens below it.
+    pub fn render(&self, show_metrics: bool) -> String {
+        let mut out = String::new();
+        self.root.render_into(&mut out, 0, show_metrics);
+        out
+    }
+
+    /// Top-level entries of the tree, directories first.
+    pub fn entries(&self) -> Vec<TreeEntry> {
+        self.root.entries()
+    }
+}
+
+impl TreeNode {
+    fn dir_mut(&mut self, parts: &[&str]) -> &mut TreeNode {
+        let mut node = self;
+        for part in parts {
+            node = node.dirs.entry(part.to_string()).or_default();
+        }
+        node
+    }
+
+    fn entries(&self) -> Vec<TreeEntry> {
+        let dirs = self.dirs.iter().map(|(name, dir)| TreeEntry {
+            name: name.clone(),
+            kind: "directory",
+            file_count: Some(dir.file_count),
+            token_count: Some(dir.token_count),
+            children: dir.entries(),
+        });
+        let files = self.files.iter().map(|name| TreeEntry {
+            name: name.clone(),
+            kind: "file",
+            file_count: None,
+            token_count: None,
+            children: Vec::new(),
+        });
+        dirs.chain(files).collect()
+    }
+
+    fn render_into(&self, out: &mut String, depth: usize, show_metrics: bool) {
+        let indent = "  ".repeat(depth);
+        for (name, dir) in &self.dirs {
+            if show_metrics {
+                out.push_str(&format!(
+                    "{}{}/ ({} {}, {} tokens)\n",
+                    indent,
+                    name,
+                    dir.file_count,
+                    if dir.file_count == 1 { "file" } else { "files" },
+                    dir.token_count
+                ));
+            } else {
+                out.push_str(&format!("{}{}/\n", indent, name));
+            }
+            dir.render_into(out, depth + 1, show_metrics);
+        }
+        for name in &self.files {
+            out.push_str(&format!("{}{}\n", indent, name));
+        }
+    }
+}
+
+fn components(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|p| !p.is_empty() && *p != ".")
+        .collect()
+}
@@ -330,6 +330,30 @@ fn test_cli_ignore() {
     }
 }
 
+#[test]
+fn test_cli_ignore_keeps_other_files() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::create_dir_all(repo_path.join("tests")).unwrap();
+    fs::write(repo_path.join("tests/it.rs"), "fn integration() {}\n").unwrap();
+    fs::write(repo_path.join("src/lib.rs"), "pub fn library() {}\n").unwrap();
+    let output_path = temp.path().join("output_7c.xml");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--ignore")
+        .arg("tests/**,src/lib.rs")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert!(!content.contains("tests/it.rs"));
+    assert!(!content.contains("src/lib.rs"));
+    assert!(content.contains("<file path=\"src/main.rs\""));
+}
+
 #[test]
 fn test_config_file() {
     let temp = TempDir::new().unwrap();
@@ -540,3 +564,2230 @@ fn test_default_ignore_lockfiles() {
         "bun.lockb should be ignored"
     );
 }
+
+#[test]
+fn test_since_last_delta_pack() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::write(repo_path.join("src/lib.rs"), "pub fn unchanged() {}\n").unwrap();
+    fs::write(repo_path.join("src/old.rs"), "pub fn old() {}\n").unwrap();
+    let output_path = temp.path().join("output_delta.xml");
+
+    // A plain pack leaves no state behind
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    assert!(!repo_path.join(".rustymix").exists());
+
+    // The first --since-last pack is complete and records the manifest
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--since-last")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    assert!(repo_path.join(".rustymix/manifest-default.json").exists());
+    assert!(fs::read_to_string(&output_path)
+        .unwrap()
+        .contains("<file path=\"src/lib.rs\""));
+
+    fs::write(repo_path.join("src/main.rs"), "fn main() { changed(); }\n").unwrap();
+    fs::write(repo_path.join("src/new.rs"), "pub fn new() {}\n").unwrap();
+    fs::remove_file(repo_path.join("src/old.rs")).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--since-last")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert!(content.contains("<changes since=\"last_pack\">"));
+    assert!(content.contains("1 added, 1 changed, 1 removed, 1 unchanged"));
+    assert!(content.contains("<file path=\"src/main.rs\""));
+    assert!(content.contains("<file path=\"src/new.rs\""));
+    assert!(content.contains("<removed path=\"src/old.rs\"/>"));
+    assert!(!content.contains("<file path=\"src/lib.rs\""));
+    assert!(!content.contains("manifest-default.json"));
+}
+
+#[test]
+fn test_directory_tree_with_empty_directories() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::create_dir_all(repo_path.join("docs/empty")).unwrap();
+    fs::create_dir_all(repo_path.join("excluded")).unwrap();
+    fs::write(repo_path.join("excluded/data.bin"), [0u8, 1, 2, 3]).unwrap();
+    let output_path = temp.path().join("output_tree.xml");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert!(content.contains("<directory_structure>\nsrc/\n  main.rs\n</directory_structure>"));
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--include-empty-directories")
+        .arg("--tree-metrics")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert!(content.contains("docs/ (0 files, 0 tokens)\n  empty/ (0 files, 0 tokens)\n"));
+    assert!(content.contains("excluded/ (0 files, 0 tokens)\n"));
+    assert!(content.contains("src/ (1 file,"));
+    assert!(!content.contains(".git/"));
+}
+
+#[test]
+fn test_top_files_summary() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::write(repo_path.join("src/big.rs"), "pub fn big() {}\n".repeat(50)).unwrap();
+    fs::write(repo_path.join("src/tiny.rs"), "//\n").unwrap();
+    let output_path = temp.path().join("output_top.md");
+
+    let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("markdown")
+        .arg("--top-files-len")
+        .arg("2")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert!(content.contains("## Top 2 Files by Token Count\n\n1. `src/big.rs`"));
+    assert!(content.contains("## Top 2 Files by Character Count\n\n1. `src/big.rs`"));
+    assert!(content.contains("2. `src/main.rs`"));
+    assert!(!content.contains("3. `src/tiny.rs`"));
+
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert!(stdout.contains("Top 2 Files by Tokens:\n  1. src/big.rs"));
+}
+
+#[test]
+fn test_json_output_schema() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::write(repo_path.join("app.py"), "def run():\n    pass\n").unwrap();
+    let output_path = temp.path().join("output_schema.json");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("json")
+        .arg("--focus")
+        .arg("src/**")
+        .arg("--intent")
+        .arg("Explain main")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+    assert_eq!(json["schemaVersion"], 2);
+    assert_eq!(json["intent"], "Explain main");
+    assert_eq!(json["summary"]["totalFiles"], 2);
+    assert_eq!(json["directoryStructure"][0]["name"], "src");
+    assert_eq!(json["directoryStructure"][0]["type"], "directory");
+
+    let files = json["files"].as_array().unwrap();
+    let main = files.iter().find(|f| f["path"] == "src/main.rs").unwrap();
+    assert_eq!(main["language"], "rust");
+    assert_eq!(main["mode"], "full");
+    assert_eq!(main["originalLineCount"], 14);
+    assert_eq!(main["hash"].as_str().unwrap().len(), 64);
+    let app = files.iter().find(|f| f["path"] == "app.py").unwrap();
+    assert_eq!(app["language"], "python");
+    assert_eq!(app["mode"], "skeleton");
+}
+
+#[test]
+fn test_jsonl_chunk_export() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let long_fn = format!(
+        "fn long() {{\n{}}}\n",
+        "    let value = compute_something_expensive(1, 2, 3);\n".repeat(40)
+    );
+    fs::write(repo_path.join("src/long.rs"), long_fn).unwrap();
+    // One line far over the limit, as in minified files
+    let minified = (0..300)
+        .map(|i| format!("\"key_{}\":{}", i, i))
+        .collect::<Vec<_>>()
+        .join(",");
+    fs::write(repo_path.join("data.json"), format!("{{{}}}\n", minified)).unwrap();
+    let output_path = temp.path().join("output_chunks.jsonl");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("jsonl")
+        .arg("--chunk-max-tokens")
+        .arg("100")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_path).unwrap();
+    let records: Vec<serde_json::Value> = content
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    let main_fn = records
+        .iter()
+        .find(|r| r["path"] == "src/main.rs" && r["symbol"] == "main")
+        .unwrap();
+    assert_eq!(main_fn["kind"], "function_item");
+    assert_eq!(main_fn["language"], "rust");
+    assert_eq!(main_fn["startLine"], 1);
+    assert_eq!(main_fn["endLine"], 3);
+    assert!(records
+        .iter()
+        .any(|r| r["symbol"] == "TestStruct" && r["kind"] == "impl_item"));
+
+    let long_parts: Vec<_> = records.iter().filter(|r| r["symbol"] == "long").collect();
+    assert!(long_parts.len() > 1, "oversized function should be split");
+    assert!(long_parts
+        .iter()
+        .all(|r| r["tokens"].as_u64().unwrap() <= 100));
+
+    let data_parts: Vec<_> = records
+        .iter()
+        .filter(|r| r["path"] == "data.json")
+        .collect();
+    assert!(data_parts.len() > 1, "an oversized line should be split");
+    assert!(data_parts
+        .iter()
+        .all(|r| r["tokens"].as_u64().unwrap() <= 100));
+    let joined: String = data_parts
+        .iter()
+        .map(|r| r["content"].as_str().unwrap())
+        .collect();
+    assert_eq!(joined, format!("{{{}}}", minified));
+}
+
+#[test]
+fn test_split_output_into_parts() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    for i in 0..4 {
+        fs::write(
+            repo_path.join(format!("src/mod_{}.rs", i)),
+            format!("pub fn function_{}() -> usize {{ {} }}\n", i, i).repeat(20),
+        )
+        .unwrap();
+    }
+    fs::write(
+        repo_path.join("src/huge.rs"),
+        "pub const VALUE: usize = 1234567;\n".repeat(200),
+    )
+    .unwrap();
+    let output_path = temp.path().join("split").join("pack.xml");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--split-output")
+        .arg("800")
+        .arg("--intent")
+        .arg("Refactor the constants")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let mut parts = Vec::new();
+    for i in 1.. {
+        let part = temp.path().join("split").join(format!("pack.{}.xml", i));
+        if !part.exists() {
+            break;
+        }
+        parts.push(fs::read_to_string(part).unwrap());
+    }
+    assert!(
+        parts.len() > 2,
+        "expected several parts, got {}",
+        parts.len()
+    );
+    let total = parts.len();
+    let bpe = tiktoken_rs::cl100k_base().unwrap();
+    for part in &parts {
+        let tokens = bpe.encode_with_special_tokens(part).len();
+        assert!(tokens <= 800, "a part takes {} tokens", tokens);
+    }
+
+    for (i, part) in parts.iter().enumerate() {
+        assert!(part.starts_with("<rustymix>"));
+        assert!(part.ends_with("</rustymix>"));
+        assert!(part.contains(&format!("This is part {} of {}", i + 1, total)));
+        if i + 1 < total {
+            assert!(part.contains("wait until you have received all"));
+            assert!(!part.contains("Refactor the constants"));
+        }
+    }
+    assert!(parts[total - 1].contains("<![CDATA[<user_request>\nRefactor the constants"));
+    assert!(parts.iter().any(|p| p.contains("src/huge.rs (lines 1-")));
+    let huge_lines: usize = parts
+        .iter()
+        .map(|p| p.matches("pub const VALUE").count())
+        .sum();
+    assert_eq!(huge_lines, 200);
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--split-output")
+        .arg("800")
+        .arg("-o")
+        .arg("-")
+        .assert()
+        .failure();
+
+    // A pack that fits in one part is the usual single file
+    let single_path = temp.path().join("single").join("pack.xml");
+    let plain_path = temp.path().join("single").join("plain.xml");
+    for (path, split) in [(&single_path, true), (&plain_path, false)] {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_rustymix"));
+        command
+            .arg(repo_path.to_str().unwrap())
+            .arg("-o")
+            .arg(path.to_str().unwrap());
+        if split {
+            command.arg("--split-output").arg("100000");
+        }
+        command.assert().success();
+    }
+    let single = fs::read_to_string(&single_path).unwrap();
+    assert!(!single.contains("This is part"));
+    assert_eq!(single, fs::read_to_string(&plain_path).unwrap());
+    assert!(!temp.path().join("single").join("pack.1.xml").exists());
+
+    // A single line longer than a part is cut within the line
+    let data_dir = temp.path().join("data");
+    fs::create_dir_all(&data_dir).unwrap();
+    let line: String = (0..800).map(|i| format!("Q{},", i)).collect();
+    fs::write(data_dir.join("data.txt"), &line).unwrap();
+    let output_path = temp.path().join("split_line").join("pack.txt");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(data_dir.to_str().unwrap())
+        .arg("--split-output")
+        .arg("800")
+        .arg("--style")
+        .arg("plain")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    let mut parts = Vec::new();
+    for i in 1.. {
+        let part = temp
+            .path()
+            .join("split_line")
+            .join(format!("pack.{}.txt", i));
+        if !part.exists() {
+            break;
+        }
+        parts.push(fs::read_to_string(part).unwrap());
+    }
+    assert!(
+        parts.len() > 2,
+        "expected several parts, got {}",
+        parts.len()
+    );
+    for part in &parts {
+        let tokens = bpe.encode_with_special_tokens(part).len();
+        assert!(tokens <= 800, "a part takes {} tokens", tokens);
+    }
+    assert!(parts[0].contains("data.txt (part of line 1 of 1)"));
+    let values: usize = parts.iter().map(|p| p.matches('Q').count()).sum();
+    assert_eq!(values, 800);
+}
+
+#[test]
+fn test_custom_template() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let template_path = temp.path().join("pack.md.j2");
+    fs::write(
+        &template_path,
+        "Intent: {{ intent }}\n\
+         {% for f in files %}\n\
+         * {{ f.path }} ({{ f.language }}, {{ f.mode }}, {{ f.tokens }} tokens)\n\
+         {% endfor %}\n\
+         Total: {{ summary.totalTokens }}\n",
+    )
+    .unwrap();
+    let output_path = temp.path().join("output_template.md");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--template")
+        .arg(template_path.to_str().unwrap())
+        .arg("--intent")
+        .arg("Summarize")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert!(content.starts_with("Intent: Summarize\n* src/main.rs (rust, full, "));
+    assert!(content.contains("Total: "));
+
+    // A broken template is reported instead of producing an empty pack
+    fs::write(&template_path, "{% for f in files %}").unwrap();
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--template")
+        .arg(template_path.to_str().unwrap())
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_block_template_writes_files_one_at_a_time() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = temp.path().join("blocks");
+    fs::create_dir_all(&repo_path).unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(repo_path.join(name), format!("content of {}\n", name)).unwrap();
+    }
+    let template_path = temp.path().join("pack.txt.j2");
+    let output_path = temp.path().join("output_blocks.txt");
+    let render = |file_block: &str| {
+        fs::write(
+            &template_path,
+            format!(
+                "{{% block header %}}\n\
+                 {{{{ files|length }}}} files{{{{ \" with content\" if files[0].content is defined }}}}\n\
+                 {{% endblock %}}\n\
+                 {{% block file %}}\n{}{{% endblock %}}\n\
+                 {{% block footer %}}\nend\n{{% endblock %}}\n",
+                file_block
+            ),
+        )
+        .unwrap();
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(repo_path.to_str().unwrap())
+            .arg("--sort")
+            .arg("path")
+            .arg("--template")
+            .arg(template_path.to_str().unwrap())
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+    };
+
+    render("{{ file.path }}: {{ file.content }}").success();
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert_eq!(
+        content,
+        "3 files\na.txt: content of a.txt\nb.txt: content of b.txt\nc.txt: content of c.txt\nend\n"
+    );
+
+    // Each file is written as soon as its block is rendered: when the last file fails, the
+    // header and the earlier files are already out
+    render("{% if file.path == \"c.txt\" %}{{ file.content|no_such_filter }}{% endif %}{{ file.path }}\n")
+        .failure();
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert_eq!(content, "3 files\na.txt\nb.txt\n");
+}
+
+#[test]
+fn test_custom_build_prompt_from_config() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let prompt_path = temp.path().join("build_prompt.txt");
+    fs::write(
+        &prompt_path,
+        "TASK: {{ intent }}\nFOCUS: {{ focus|join(\" | \") }}\nBUDGET: {{ totalTokens }} tokens in {{ totalFiles }} files\nReply with a JSON patch.",
+    )
+    .unwrap();
+    let config_path = temp.path().join("prompt_config.json");
+    fs::write(
+        &config_path,
+        format!(
+            r#"{{ "prompts": {{ "buildPath": {:?} }} }}"#,
+            prompt_path.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    let output_path = temp.path().join("output_prompt.xml");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--config")
+        .arg(config_path.to_str().unwrap())
+        .arg("--focus")
+        .arg("src/main.rs, src/lib.rs")
+        .arg("--intent")
+        .arg("Add logging")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert!(content.contains("TASK: Add logging\nFOCUS: src/main.rs | src/lib.rs\nBUDGET: "));
+    assert!(content.contains("tokens in 1 files\nReply with a JSON patch.\n"));
+    assert!(!content.contains("THE USER WANTS TO"));
+}
+
+#[test]
+fn test_json_survey_reply_round_trip() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let survey_path = temp.path().join("output_survey.xml");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--intent")
+        .arg("Add logging")
+        .arg("--survey-format")
+        .arg("json")
+        .arg("-o")
+        .arg(survey_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let survey = fs::read_to_string(&survey_path).unwrap();
+    assert!(survey.contains("\"title\": \"RustymixSurveyReply\""));
+    assert!(!survey.contains("SINGLE LINE"));
+
+    // A reply wrapped in a markdown fence, as models tend to send it
+    let reply_path = temp.path().join("reply.json");
+    fs::write(
+        &reply_path,
+        "```json\n{\"focus\": [\"src/*.rs\"], \"symbols\": [\"main\"], \"rationale\": \"Entry point.\"}\n```\n",
+    )
+    .unwrap();
+    let build_path = temp.path().join("output_build.xml");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--intent")
+        .arg("Add logging")
+        .arg("--survey-reply")
+        .arg(reply_path.to_str().unwrap())
+        .arg("-o")
+        .arg(build_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let build = fs::read_to_string(&build_path).unwrap();
+    assert!(build.contains("Attached is the CONTEXT PACK."));
+    assert!(build.contains("mode=\"full\""));
+    assert!(build.contains("fn main()"));
+
+    // Focus patterns that select nothing, and unknown fields, are rejected
+    fs::write(
+        &reply_path,
+        r#"{"focus": ["docs/**"], "rationale": "Docs.", "confidence": 0.9}"#,
+    )
+    .unwrap();
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--survey-reply")
+        .arg(reply_path.to_str().unwrap())
+        .arg("-o")
+        .arg(build_path.to_str().unwrap())
+        .assert()
+        .failure();
+
+    fs::write(
+        &reply_path,
+        r#"{"focus": ["docs/**"], "rationale": "Docs."}"#,
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--survey-reply")
+        .arg(reply_path.to_str().unwrap())
+        .arg("-o")
+        .arg(build_path.to_str().unwrap())
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&output.get_output().stderr).to_string();
+    assert!(stderr.contains("focus pattern \"docs/**\" matches no packed file"));
+}
+
+#[test]
+fn test_xml_output_parses_for_all_fixtures() {
+    let temp = TempDir::new().unwrap();
+    let mut repos = vec![
+        create_rust_repo(temp.path()),
+        create_ts_repo(temp.path()),
+        create_py_repo(temp.path()),
+        create_go_repo(temp.path()),
+        create_mixed_repo(temp.path()),
+    ];
+
+    // Markup-like characters in paths, content, header and instructions
+    let awkward = temp.path().join("awkward_repo");
+    fs::create_dir_all(awkward.join("a&b")).unwrap();
+    fs::write(
+        awkward.join("a&b/it's \"quoted\".txt"),
+        "if a < b && c > d { \"]]>\" }\ncontrol:\u{1}\n",
+    )
+    .unwrap();
+    fs::write(awkward.join("cdata.xml"), "<![CDATA[ x ]]>\n").unwrap();
+    init_git_repo(&awkward);
+    repos.push(awkward);
+
+    let instructions_path = temp.path().join("instructions.txt");
+    fs::write(&instructions_path, "Use <b>bold</b> & keep ]]> intact").unwrap();
+
+    for (i, repo) in repos.iter().enumerate() {
+        for cdata in [false, true] {
+            let output_path = temp.path().join(format!("output_{}_{}.xml", i, cdata));
+            let mut cmd = Command::new(env!("CARGO_BIN_EXE_rustymix"));
+            cmd.arg(repo.to_str().unwrap())
+                .arg("--header-text")
+                .arg("Q&A <draft>")
+                .arg("--instruction-file-path")
+                .arg(instructions_path.to_str().unwrap())
+                .arg("--intent")
+                .arg("Compare a < b")
+                .arg("--include-logs")
+                .arg("-o")
+                .arg(output_path.to_str().unwrap());
+            if cdata {
+                cmd.arg("--xml-cdata");
+            }
+            cmd.assert().success();
+
+            let content = fs::read_to_string(&output_path).unwrap();
+            let doc = roxmltree::Document::parse(&content).unwrap_or_else(|e| {
+                panic!(
+                    "invalid XML for {} (cdata: {}): {}",
+                    repo.display(),
+                    cdata,
+                    e
+                )
+            });
+            let root = doc.root_element();
+            let header = root.children().find(|n| n.has_tag_name("header")).unwrap();
+            assert_eq!(header.text(), Some("Q&A <draft>"));
+            // The generated prompt keeps its markup, with the intent inside it verbatim
+            let prompt = root.children().find(|n| n.has_tag_name("prompt")).unwrap();
+            assert!(prompt
+                .text()
+                .unwrap()
+                .starts_with("<user_request>\nCompare a < b\n</user_request>"));
+            let instruction = root
+                .descendants()
+                .find(|n| n.has_tag_name("instruction"))
+                .unwrap();
+            assert_eq!(
+                instruction.text(),
+                Some("Use <b>bold</b> & keep ]]> intact")
+            );
+        }
+    }
+
+    let content = fs::read_to_string(temp.path().join("output_5_true.xml")).unwrap();
+    let doc = roxmltree::Document::parse(&content).unwrap();
+    let file = doc
+        .descendants()
+        .find(|n| n.attribute("path") == Some("a&b/it's \"quoted\".txt") && n.has_attribute("mode"))
+        .unwrap();
+    let text: String = file.children().filter_map(|n| n.text()).collect();
+    assert!(text.contains("if a < b && c > d { \"]]>\" }"));
+    assert!(text.contains("control:\u{fffd}"));
+}
+
+#[test]
+fn test_markdown_fences_and_plain_separator_survive_file_content() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::write(
+        repo_path.join("README.md"),
+        "# Demo\n\n````text\n```rust\nfn nested() {}\n```\n````\n",
+    )
+    .unwrap();
+    fs::write(
+        repo_path.join("notes.txt"),
+        "File: fake.rs\n------------------------------\nnot a real file\n",
+    )
+    .unwrap();
+
+    let md_path = temp.path().join("output.md");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("markdown")
+        .arg("-o")
+        .arg(md_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&md_path).unwrap();
+    assert!(content.contains("`````markdown\n# Demo\n"));
+    assert!(content.contains("```\n````\n\n`````\n"));
+    assert!(content.contains("```rust\nfn main()"));
+    assert!(content.contains("```\nFile: fake.rs"));
+
+    let plain_path = temp.path().join("output.txt");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("plain")
+        .arg("-o")
+        .arg(plain_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&plain_path).unwrap();
+    let separator = format!("\n{}\n", "-".repeat(31));
+    assert!(content.contains(&format!("File: src/main.rs\nmode: full{}", separator)));
+    assert!(content.contains(&format!(
+        "File: notes.txt\nmode: full{}File: fake.rs\n",
+        separator
+    )));
+}
+
+#[test]
+fn test_file_metadata_headers() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    std::process::Command::new("git")
+        .args(["add", "."])
+        .current_dir(&repo_path)
+        .output()
+        .unwrap();
+    std::process::Command::new("git")
+        .args(["commit", "-m", "Initial commit"])
+        .current_dir(&repo_path)
+        .output()
+        .unwrap();
+    let metadata = "language,lines,tokens,bytes,commit,mode,truncated";
+
+    let mut outputs = Vec::new();
+    for (style, ext) in [
+        ("xml", "xml"),
+        ("markdown", "md"),
+        ("plain", "txt"),
+        ("json", "json"),
+    ] {
+        let output_path = temp.path().join(format!("output_meta.{}", ext));
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(repo_path.to_str().unwrap())
+            .arg("--style")
+            .arg(style)
+            .arg("--file-metadata")
+            .arg(metadata)
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+            .success();
+        outputs.push(fs::read_to_string(&output_path).unwrap());
+    }
+
+    assert!(outputs[0].contains(
+        "<file path=\"src/main.rs\" mode=\"full\" language=\"rust\" lines=\"14\" tokens=\""
+    ));
+    assert!(outputs[0].contains("bytes=\"181\" truncated=\"false\" lastCommit=\""));
+    assert!(outputs[0].contains("lastCommitAuthor=\"Test User\">"));
+
+    assert!(outputs[1]
+        .contains("## File: src/main.rs [FULL TEXT]\nlanguage: rust | lines: 14 | tokens: "));
+    assert!(outputs[1].contains("| bytes: 181 | truncated: false | lastCommit: "));
+    assert!(outputs[2]
+        .contains("File: src/main.rs\nmode: full | language: rust | lines: 14 | tokens: "));
+
+    let json: serde_json::Value = serde_json::from_str(&outputs[3]).unwrap();
+    let meta = &json["files"][0]["metadata"];
+    assert_eq!(meta["language"], "rust");
+    assert_eq!(meta["bytes"], 181);
+    assert_eq!(meta["truncated"], false);
+    assert_eq!(meta["lastCommitAuthor"], "Test User");
+    assert_eq!(meta["lastCommit"].as_str().unwrap().len(), 7);
+
+    // A newer commit and an untracked file: the older commit is still found for main.rs
+    let initial = meta["lastCommit"].as_str().unwrap().to_string();
+    fs::write(repo_path.join("src/lib.rs"), "pub fn lib() {}\n").unwrap();
+    std::process::Command::new("git")
+        .args(["add", "src/lib.rs"])
+        .current_dir(&repo_path)
+        .output()
+        .unwrap();
+    std::process::Command::new("git")
+        .args(["commit", "-m", "Add lib"])
+        .current_dir(&repo_path)
+        .output()
+        .unwrap();
+    fs::write(repo_path.join("src/new.rs"), "pub fn new() {}\n").unwrap();
+    let output_path = temp.path().join("output_meta_commit.json");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("json")
+        .arg("--file-metadata")
+        .arg("commit")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+    let meta = |path: &str| {
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| f["path"] == path)
+            .unwrap()["metadata"]
+            .clone()
+    };
+    assert_eq!(meta("src/main.rs")["lastCommit"], initial.as_str());
+    assert_ne!(meta("src/lib.rs")["lastCommit"], initial.as_str());
+    assert!(meta("src/new.rs").get("lastCommit").is_none());
+
+    // Without `mode` in the headers, the build prompt does not point at it
+    let output_path = temp.path().join("output_meta_prompt.xml");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--file-metadata")
+        .arg("language")
+        .arg("--focus")
+        .arg("src/main.rs")
+        .arg("--intent")
+        .arg("Add logging")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    let content = fs::read_to_string(&output_path).unwrap();
+    assert!(!content.contains("mode="));
+    assert!(content.contains("The specific files you requested are included in full."));
+}
+
+#[test]
+fn test_yaml_and_html_styles() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::write(
+        repo_path.join("src/lib.rs"),
+        "pub fn add(a: i32) -> i32 { a }\n",
+    )
+    .unwrap();
+
+    let yaml_path = temp.path().join("output.yaml");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("yaml")
+        .arg("-o")
+        .arg(yaml_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let yaml: serde_norway::Value =
+        serde_norway::from_str(&fs::read_to_string(&yaml_path).unwrap()).unwrap();
+    assert_eq!(yaml["schemaVersion"], 2);
+    assert_eq!(yaml["summary"]["totalFiles"], 2);
+    let files = yaml["files"].as_sequence().unwrap();
+    assert!(files
+        .iter()
+        .any(|f| f["path"] == "src/main.rs" && f["language"] == "rust"));
+
+    fs::write(
+        repo_path.join("src/script.py"),
+        "def f():\n    \"\"\"Say \"hi\" # not a comment\"\"\"\n    return 1\n",
+    )
+    .unwrap();
+    fs::write(
+        repo_path.join("src/nested.rs"),
+        "/* outer /* inner */ still comment */\nfn nested() {}\n",
+    )
+    .unwrap();
+    let html_path = temp.path().join("output.html");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("html")
+        .arg("--focus")
+        .arg("src/main.rs,src/script.py,src/nested.rs")
+        .arg("-o")
+        .arg(html_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let html = fs::read_to_string(&html_path).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.trim_end().ends_with("</html>"));
+    assert!(!html.contains("<script src") && !html.contains("<link"));
+    assert!(html.contains("<summary class=\"dir\">src/ <span class=\"muted\">(4 files, "));
+    assert!(html.contains("<span class=\"badge full\">FULL</span>"));
+    assert!(html.contains("<span class=\"badge skeleton\">SKELETON</span>"));
+    assert!(html.contains("<span class=\"k\">fn</span> main()"));
+    assert!(html.contains("<span class=\"s\">&quot;Hello, world!&quot;</span>"));
+    assert!(html.contains(
+        "<span class=\"s\">&quot;&quot;&quot;Say &quot;hi&quot; # not a comment&quot;&quot;&quot;</span>"
+    ));
+    assert!(html.contains(
+        "<span class=\"c\">/* outer /* inner */ still comment */</span>\n<span class=\"k\">fn</span> nested()"
+    ));
+    assert!(html.contains("<span class=\"bar\" title="));
+}
+
+#[test]
+fn test_stream_output_to_stdout() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let file_path = temp.path().join("output_file.xml");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("-o")
+        .arg(file_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("-o")
+        .arg("-")
+        .assert()
+        .success();
+    let output = assert.get_output();
+    let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+    let stderr = String::from_utf8(output.stderr.clone()).unwrap();
+
+    // stdout carries exactly the pack; progress and summary go to stderr
+    assert_eq!(stdout, fs::read_to_string(&file_path).unwrap());
+    assert!(stderr.contains("Total Files: 1"));
+    assert!(!stdout.contains("Total Files"));
+
+    let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("jsonl")
+        .arg("-o")
+        .arg("-")
+        .assert()
+        .success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    for line in stdout.lines() {
+        let record: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(record["path"], "src/main.rs");
+    }
+}
+
+#[test]
+fn test_multiple_styles_in_one_run() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let output_path = temp.path().join("out").join("pack.xml");
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("xml,markdown,json")
+        .arg("--intent")
+        .arg("Explain main")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+
+    let out_dir = temp.path().join("out");
+    let xml = fs::read_to_string(out_dir.join("pack.xml")).unwrap();
+    let md = fs::read_to_string(out_dir.join("pack.md")).unwrap();
+    let json = fs::read_to_string(out_dir.join("pack.json")).unwrap();
+    assert!(xml.starts_with("<rustymix>"));
+    assert!(md.contains("## File: src/main.rs"));
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["intent"], "Explain main");
+    // The prompt is added once per file, not accumulated across styles
+    assert_eq!(md.matches("THE USER WANTS TO").count(), 1);
+
+    // `output.styles` from the config, combined with bulk intents
+    let intent_dir = temp.path().join("intents");
+    fs::create_dir_all(&intent_dir).unwrap();
+    fs::write(intent_dir.join("fix_bug.txt"), "Fix the bug.").unwrap();
+    let config_path = temp.path().join("styles_config.json");
+    fs::write(
+        &config_path,
+        r#"{ "output": { "styles": ["markdown", "plain"] } }"#,
+    )
+    .unwrap();
+    let results = temp.path().join("results");
+    fs::create_dir_all(&results).unwrap();
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--config")
+        .arg(config_path.to_str().unwrap())
+        .arg("--intent")
+        .arg(intent_dir.to_str().unwrap())
+        .arg("-o")
+        .arg(results.to_str().unwrap())
+        .assert()
+        .success();
+
+    assert!(results.join("rustymix-fix_bug.md").exists());
+    assert!(results.join("rustymix-fix_bug.txt").exists());
+    assert!(!results.join("rustymix-fix_bug.xml").exists());
+
+    // Several styles cannot share stdout
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("xml,markdown")
+        .arg("-o")
+        .arg("-")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_git_diff_modes_and_cross_references() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&repo_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+    };
+    git(&["branch", "base"]);
+    fs::write(repo_path.join("src/lib.rs"), "pub fn lib() {}\n").unwrap();
+    git(&["add", "src/lib.rs"]);
+    git(&["commit", "-m", "Add lib"]);
+
+    // One staged change, one unstaged change, one untracked file
+    fs::write(repo_path.join("src/staged.rs"), "pub fn staged() {}\n").unwrap();
+    git(&["add", "src/staged.rs"]);
+    fs::write(
+        repo_path.join("src/lib.rs"),
+        "pub fn lib() {}\npub fn more() {}\n",
+    )
+    .unwrap();
+    fs::write(repo_path.join("notes.txt"), "untracked\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output_path = temp.path().join("output_diff.json");
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(repo_path.to_str().unwrap())
+            .args(args)
+            .arg("--style")
+            .arg("json")
+            .arg("--ignore")
+            .arg("notes.txt")
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+            .success();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        json["gitDiffFiles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| {
+                format!(
+                    "{} {} {}",
+                    d["path"].as_str().unwrap(),
+                    d["status"].as_str().unwrap(),
+                    d["inPack"]
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        run(&["--include-diffs"]),
+        [
+            "src/lib.rs modified true",
+            "src/staged.rs added true",
+            "notes.txt added false"
+        ]
+    );
+    // Both backends report untracked files under their real names
+    fs::write(repo_path.join("notés.txt"), "untracked\n").unwrap();
+    for backend in ["cli", "native"] {
+        assert_eq!(
+            run(&["--include-diffs", "--git-backend", backend]),
+            [
+                "src/lib.rs modified true",
+                "src/staged.rs added true",
+                "notes.txt added false",
+                "notés.txt added true"
+            ]
+        );
+    }
+    fs::remove_file(repo_path.join("notés.txt")).unwrap();
+
+    // Files of a nested repository are not untracked additions
+    let nested = repo_path.join("vendor/nested");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(nested.join("inner.rs"), "pub fn inner() {}\n").unwrap();
+    let output = std::process::Command::new("git")
+        .args(["init", "-q"])
+        .current_dir(&nested)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    for backend in ["cli", "native"] {
+        assert_eq!(
+            run(&["--include-diffs", "--git-backend", backend]),
+            [
+                "src/lib.rs modified true",
+                "src/staged.rs added true",
+                "notes.txt added false"
+            ]
+        );
+    }
+    fs::remove_dir_all(repo_path.join("vendor")).unwrap();
+    assert_eq!(run(&["--diff-staged"]), ["src/staged.rs added true"]);
+    assert_eq!(
+        run(&["--diff-unstaged"]),
+        ["src/lib.rs modified true", "notes.txt added false"]
+    );
+    assert_eq!(
+        run(&["--diff-range", "base..HEAD"]),
+        ["src/lib.rs added true"]
+    );
+    assert_eq!(
+        run(&["--diff-base", "base", "--diff-staged"]),
+        ["src/lib.rs added true", "src/staged.rs added true"]
+    );
+
+    // XML hunks point back at their file entries
+    let output_path = temp.path().join("output_diff.xml");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--diff-staged")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    let xml = fs::read_to_string(&output_path).unwrap();
+    assert!(xml.contains("<file path=\"src/staged.rs\" mode=\"full\">"));
+    assert!(xml.contains(
+        "<file_diff path=\"src/staged.rs\" status=\"added\" additions=\"1\" deletions=\"0\" in_pack=\"true\">"
+    ));
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--diff-base")
+        .arg("no-such-ref")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_focus_changed_files() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::write(
+        repo_path.join("src/util.rs"),
+        "pub fn util() {\n    let x = 1;\n}\n",
+    )
+    .unwrap();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&repo_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+    };
+    git(&["add", "src/util.rs"]);
+    git(&["commit", "-m", "Add util"]);
+    git(&["tag", "base"]);
+    // Committed on the feature branch, staged, and untracked; main.rs and util.rs untouched
+    git(&["checkout", "-b", "feature"]);
+    fs::write(
+        repo_path.join("src/lib.rs"),
+        "pub fn lib() {\n    let y = 2;\n}\n",
+    )
+    .unwrap();
+    git(&["add", "src/lib.rs"]);
+    git(&["commit", "-m", "Add lib"]);
+    fs::write(
+        repo_path.join("src/staged.rs"),
+        "pub fn staged() {\n    let z = 3;\n}\n",
+    )
+    .unwrap();
+    git(&["add", "src/staged.rs"]);
+    fs::write(
+        repo_path.join("src/new.rs"),
+        "pub fn new() {\n    let w = 4;\n}\n",
+    )
+    .unwrap();
+
+    let modes = |args: &[&str]| {
+        let output_path = temp.path().join("output_focus_changed.json");
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .current_dir(&repo_path)
+            .args(args)
+            .arg("--compress")
+            .arg("--style")
+            .arg("json")
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+            .success();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                format!(
+                    "{} {}",
+                    f["path"].as_str().unwrap(),
+                    f["mode"].as_str().unwrap()
+                )
+            })
+            .collect::<std::collections::BTreeSet<_>>()
+    };
+
+    // The default base is the repository's default branch
+    let expected: std::collections::BTreeSet<String> = [
+        "src/lib.rs full",
+        "src/main.rs skeleton",
+        "src/new.rs full",
+        "src/staged.rs full",
+        "src/util.rs skeleton",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    assert_eq!(modes(&["--focus-changed"]), expected);
+    // A directory after the flag is not taken for the ref
+    assert_eq!(modes(&["--focus-changed", "."]), expected);
+
+    // An explicit base further back, combined with --focus
+    let expected: std::collections::BTreeSet<String> = [
+        "src/lib.rs full",
+        "src/main.rs full",
+        "src/new.rs full",
+        "src/staged.rs full",
+        "src/util.rs full",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    assert_eq!(
+        modes(&["--focus-changed=base~1", "--focus", "src/main.rs"]),
+        expected
+    );
+}
+
+#[test]
+fn test_review_pack_for_commit_range() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::write(
+        repo_path.join("src/util.rs"),
+        "pub fn util() {\n    let x = 1;\n}\n",
+    )
+    .unwrap();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&repo_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+    };
+    git(&["add", "src/util.rs"]);
+    git(&["commit", "-m", "Add util"]);
+    git(&["tag", "base"]);
+    git(&["checkout", "-b", "feature"]);
+    let main_rs = fs::read_to_string(repo_path.join("src/main.rs")).unwrap();
+    fs::write(
+        repo_path.join("src/main.rs"),
+        main_rs.replace("Self { field: 0 }", "Self { field: 42 }"),
+    )
+    .unwrap();
+    git(&[
+        "commit",
+        "-am",
+        "Start fields at 42\n\nThe answer is a better default.",
+    ]);
+    git(&["checkout", "base"]);
+    // Uncommitted changes on the checked-out revision stay out of the review
+    fs::write(repo_path.join("src/util.rs"), "pub fn dirty() {}\n").unwrap();
+
+    let output_path = temp.path().join("output_review.json");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg("review")
+        .arg("base..feature")
+        .arg(repo_path.to_str().unwrap())
+        .arg("--style")
+        .arg("json")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+
+    let file = |path: &str| {
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| f["path"] == path)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(file("src/main.rs")["mode"], "full");
+    assert!(file("src/main.rs")["content"]
+        .as_str()
+        .unwrap()
+        .contains("field: 42"));
+    assert_eq!(file("src/util.rs")["mode"], "skeleton");
+    assert!(!json.to_string().contains("dirty"));
+
+    let prompt = json["prompt"].as_str().unwrap();
+    assert!(prompt.contains("Review the change set base..feature (1 commit(s), 1 file(s) changed)"));
+    assert!(prompt.contains("- src/main.rs (modified, lines 11): new"));
+    assert!(json["gitDiff"]
+        .as_str()
+        .unwrap()
+        .contains("+        Self { field: 42 }"));
+    assert!(json.get("gitLog").is_none());
+    let subjects: Vec<&str> = json["gitLogCommits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["subject"].as_str().unwrap())
+        .collect();
+    assert_eq!(subjects, ["Start fields at 42"]);
+    assert_eq!(
+        json["gitLogCommits"][0]["body"],
+        "The answer is a better default."
+    );
+
+    // The built-in backend reviews without a git executable
+    let empty_path = temp.path().join("no_git");
+    fs::create_dir_all(&empty_path).unwrap();
+    let native_path = temp.path().join("output_review_native.json");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg("review")
+        .arg("base..feature")
+        .arg(repo_path.to_str().unwrap())
+        .arg("--git-backend")
+        .arg("native")
+        .arg("--style")
+        .arg("json")
+        .arg("-o")
+        .arg(native_path.to_str().unwrap())
+        .env("PATH", &empty_path)
+        .assert()
+        .success();
+    let native: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&native_path).unwrap()).unwrap();
+    for field in ["prompt", "files", "gitDiff", "gitLogCommits"] {
+        assert_eq!(native[field], json[field], "{}", field);
+    }
+
+    // No worktree was registered in the repository
+    let worktrees = std::process::Command::new("git")
+        .args(["worktree", "list"])
+        .current_dir(&repo_path)
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&worktrees.stdout).lines().count(),
+        1
+    );
+}
+
+#[test]
+fn test_pack_subcommand_for_directory_named_review() {
+    let temp = TempDir::new().unwrap();
+    let review_dir = temp.path().join("review");
+    fs::create_dir_all(&review_dir).unwrap();
+    fs::write(review_dir.join("notes.txt"), "reviewed notes\n").unwrap();
+
+    // Without a subcommand, `review` is taken for the review command and wants a range
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .current_dir(temp.path())
+        .arg("review")
+        .assert()
+        .failure();
+
+    for args in [&["pack", "review"][..], &["--", "review"][..]] {
+        let output_path = temp.path().join("output_pack.xml");
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .current_dir(temp.path())
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .args(args)
+            .assert()
+            .success();
+        let content = fs::read_to_string(&output_path).unwrap();
+        assert!(
+            content.contains("<file path=\"notes.txt\" mode=\"full\">"),
+            "{:?}",
+            args
+        );
+        assert!(content.contains("reviewed notes"));
+    }
+}
+
+#[test]
+fn test_pack_git_ref_without_checkout() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&repo_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+    };
+    fs::create_dir_all(repo_path.join("build")).unwrap();
+    fs::write(repo_path.join("build/out.txt"), "generated\n").unwrap();
+    fs::write(repo_path.join("notes.md"), "# Notes v1\n").unwrap();
+    fs::write(repo_path.join(".gitignore"), "build/\n").unwrap();
+    git(&["add", "-f", "."]);
+    git(&["commit", "-m", "v1"]);
+    git(&["tag", "v1.0.0"]);
+
+    // Later commits and uncommitted edits must not leak into the v1.0.0 pack
+    fs::write(repo_path.join("notes.md"), "# Notes v2\n").unwrap();
+    fs::write(repo_path.join("src/lib.rs"), "pub fn v2() {}\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "v2"]);
+    fs::write(repo_path.join("src/main.rs"), "fn main() { /* dirty */ }\n").unwrap();
+
+    let output_path = temp.path().join("output_ref.json");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--ref")
+        .arg("v1.0.0")
+        .arg("--style")
+        .arg("json")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+    let files: Vec<(&str, &str)> = json["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| (f["path"].as_str().unwrap(), f["content"].as_str().unwrap()))
+        .collect();
+    let paths: std::collections::BTreeSet<&str> = files.iter().map(|(p, _)| *p).collect();
+    assert_eq!(
+        paths,
+        ["notes.md", "src/main.rs"]
+            .into_iter()
+            .collect::<std::collections::BTreeSet<_>>()
+    );
+    assert!(files.iter().any(|(_, c)| c.contains("# Notes v1")));
+    assert!(files.iter().any(|(_, c)| c.contains("Hello, world!")));
+    assert!(!json.to_string().contains("dirty"));
+
+    // The working tree is untouched
+    assert_eq!(
+        fs::read_to_string(repo_path.join("src/main.rs")).unwrap(),
+        "fn main() { /* dirty */ }\n"
+    );
+
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--ref")
+        .arg("no-such-tag")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_native_git_backend_without_git_executable() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    fs::write(
+        repo_path.join("src/main.rs"),
+        "fn main() {\n    println!(\"Hello, native!\");\n}\n",
+    )
+    .unwrap();
+    fs::write(repo_path.join("src/new.rs"), "pub fn added() {}\n").unwrap();
+    // No git on the PATH: everything below must be done in-process
+    let empty_path = temp.path().join("no_git");
+    fs::create_dir_all(&empty_path).unwrap();
+
+    let output_path = temp.path().join("output_native.json");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--include-diffs")
+        .arg("--include-logs")
+        .arg("--file-metadata")
+        .arg("commit")
+        .arg("--style")
+        .arg("json")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .env("PATH", &empty_path)
+        .env("RUSTYMIX_GIT_BACKEND", "native")
+        .assert()
+        .success();
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+    let diffs: Vec<(&str, &str)> = json["gitDiffFiles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| (d["path"].as_str().unwrap(), d["status"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        diffs,
+        [("src/main.rs", "modified"), ("src/new.rs", "added")]
+    );
+    let diff = json["gitDiff"].as_str().unwrap();
+    assert!(diff.contains("-    println!(\"Hello, world!\");"));
+    assert!(diff.contains("+    println!(\"Hello, native!\");"));
+    assert_eq!(json["gitLogCommits"][0]["author"], "Test User");
+    assert_eq!(json["gitLogCommits"][0]["subject"], "Initial commit");
+    let main = json["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|f| f["path"] == "src/main.rs")
+        .unwrap();
+    assert_eq!(main["metadata"]["lastCommitAuthor"], "Test User");
+
+    // Local repositories are cloned in-process too
+    let clone_output = temp.path().join("output_clone.json");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg("--remote")
+        .arg(format!("file://{}", repo_path.display()))
+        .arg("--no-cache")
+        .arg("--style")
+        .arg("json")
+        .arg("-o")
+        .arg(clone_output.to_str().unwrap())
+        .env("PATH", &empty_path)
+        .env("RUSTYMIX_GIT_BACKEND", "native")
+        .assert()
+        .success();
+    let clone = fs::read_to_string(&clone_output).unwrap();
+    assert!(clone.contains("Hello, world!"));
+    assert!(!clone.contains("Hello, native!"));
+}
+
+#[test]
+fn test_structured_git_log_with_focus_and_diffs() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&repo_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+    };
+    fs::write(repo_path.join("src/util.rs"), "pub fn util() {}\n").unwrap();
+    git(&["add", "."]);
+    git(&[
+        "commit",
+        "-m",
+        "Add util",
+        "-m",
+        "Shared helpers live here.",
+    ]);
+    fs::write(repo_path.join("notes.md"), "# Notes\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Add notes"]);
+
+    for backend in ["native", "cli"] {
+        let output_path = temp.path().join(format!("output_log_{}.json", backend));
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(repo_path.to_str().unwrap())
+            .arg("--focus")
+            .arg("src/util.rs")
+            .arg("--log-focused")
+            .arg("--log-diffs")
+            .arg("--git-backend")
+            .arg(backend)
+            .arg("--style")
+            .arg("json")
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+            .success();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        let commits = json["gitLogCommits"].as_array().unwrap();
+        assert_eq!(commits.len(), 1, "{}: {:?}", backend, commits);
+        let commit = &commits[0];
+        assert_eq!(commit["subject"], "Add util");
+        assert_eq!(commit["body"], "Shared helpers live here.");
+        assert_eq!(commit["author"], "Test User");
+        assert_eq!(commit["email"], "test@example.com");
+        assert_eq!(commit["files"], serde_json::json!(["src/util.rs"]));
+        assert!(commit["patch"]
+            .as_str()
+            .unwrap()
+            .contains("+pub fn util() {}"));
+    }
+
+    // The count keeps the most recent commits; XML gets one element per commit
+    let output_path = temp.path().join("output_log.xml");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--log-count")
+        .arg("2")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    let content = fs::read_to_string(&output_path).unwrap();
+    let doc = roxmltree::Document::parse(&content).unwrap();
+    let subjects: Vec<&str> = doc
+        .descendants()
+        .filter(|n| n.has_tag_name("commit"))
+        .map(|c| {
+            c.children()
+                .find(|n| n.has_tag_name("subject"))
+                .and_then(|n| n.text())
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(subjects, ["Add notes", "Add util"]);
+
+    // Without focus patterns --log-focused changes nothing
+    for backend in ["native", "cli"] {
+        let output_path = temp
+            .path()
+            .join(format!("output_unfocused_{}.json", backend));
+        let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(repo_path.to_str().unwrap())
+            .arg("--log-focused")
+            .arg("--git-backend")
+            .arg(backend)
+            .arg("--style")
+            .arg("json")
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+            .success();
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+        assert!(stderr.contains("--log-focused has no effect without --focus"));
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        assert_eq!(json["gitLogCommits"].as_array().unwrap().len(), 3);
+    }
+}
+
+#[test]
+fn test_non_ascii_paths_in_log_and_churn() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&repo_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+    };
+    for version in 1..=3 {
+        fs::write(
+            repo_path.join("src/é.rs"),
+            format!("pub const V: u8 = {};\n", version),
+        )
+        .unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-m", &format!("Accent v{}", version)]);
+    }
+
+    for backend in ["native", "cli"] {
+        let output_path = temp.path().join(format!("output_accent_{}.json", backend));
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(repo_path.to_str().unwrap())
+            .arg("--log-count")
+            .arg("1")
+            .arg("--log-diffs")
+            .arg("--sort")
+            .arg("churn")
+            .arg("--git-backend")
+            .arg(backend)
+            .arg("--style")
+            .arg("json")
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+            .success();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        let commit = &json["gitLogCommits"][0];
+        assert_eq!(
+            commit["files"],
+            serde_json::json!(["src/é.rs"]),
+            "{}",
+            backend
+        );
+        assert!(
+            commit["patch"]
+                .as_str()
+                .unwrap()
+                .contains("+pub const V: u8 = 3;"),
+            "{}",
+            backend
+        );
+        // Changed most often, so last
+        let last = json["files"].as_array().unwrap().last().unwrap();
+        assert_eq!(last["path"], "src/é.rs", "{}", backend);
+    }
+}
+
+#[test]
+fn test_file_history_blames_recent_line_ranges() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&repo_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+    };
+    fs::write(repo_path.join("src/lib.rs"), "a\nb\nc\nd\ne\nf\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Add lib"]);
+    fs::write(repo_path.join("src/lib.rs"), "a\nB\nC\nd\ne\nF\n").unwrap();
+    git(&["commit", "-am", "Tweak lib"]);
+    fs::write(repo_path.join("src/lib.rs"), "a\nB\nC\nD\ne\nF\n").unwrap();
+    git(&["commit", "-am", "Tweak lib again"]);
+
+    for backend in ["native", "cli"] {
+        let output_path = temp.path().join(format!("output_history_{}.json", backend));
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(repo_path.to_str().unwrap())
+            .arg("--focus")
+            .arg("src/lib.rs")
+            .arg("--file-history")
+            .arg("2")
+            .arg("--git-backend")
+            .arg(backend)
+            .arg("--style")
+            .arg("json")
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+            .success();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        let histories = json["fileHistory"].as_array().unwrap();
+        assert_eq!(histories.len(), 1, "{}: {:?}", backend, histories);
+        let history = &histories[0];
+        assert_eq!(history["path"], "src/lib.rs");
+        let commits = history["commits"].as_array().unwrap();
+        let subjects: Vec<&str> = commits
+            .iter()
+            .map(|c| c["subject"].as_str().unwrap())
+            .collect();
+        assert_eq!(subjects, ["Tweak lib again", "Tweak lib"], "{}", backend);
+        let ranges: Vec<(u64, u64, &str)> = history["recentLines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["start"].as_u64().unwrap(),
+                    r["end"].as_u64().unwrap(),
+                    r["hash"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        let (latest, previous) = (
+            commits[0]["hash"].as_str().unwrap(),
+            commits[1]["hash"].as_str().unwrap(),
+        );
+        assert_eq!(
+            ranges,
+            [(2, 3, previous), (4, 4, latest), (6, 6, previous)],
+            "{}",
+            backend
+        );
+    }
+
+    // Without focus there is no history to attach
+    let output_path = temp.path().join("output_unfocused.json");
+    let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg(repo_path.to_str().unwrap())
+        .arg("--file-history")
+        .arg("2")
+        .arg("--style")
+        .arg("json")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .assert()
+        .success();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+    assert!(stderr.contains("--file-history only covers focused files"));
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+    assert!(json["fileHistory"].is_null());
+}
+
+#[test]
+fn test_sort_orders() {
+    let temp = TempDir::new().unwrap();
+    let dir = temp.path().join("project");
+    fs::create_dir_all(dir.join("src/net")).unwrap();
+    fs::create_dir_all(dir.join("web")).unwrap();
+    fs::create_dir_all(dir.join("app")).unwrap();
+    fs::write(
+        dir.join("src/main.rs"),
+        "mod config;\nmod net;\n\nfn main() {}\n",
+    )
+    .unwrap();
+    fs::write(dir.join("src/config.rs"), "pub struct Config;\n").unwrap();
+    fs::write(
+        dir.join("src/net.rs"),
+        "mod client;\nuse crate::config::Config;\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/net/client.rs"),
+        "use super::super::config::Config;\npub fn connect(_: Config) {}\n",
+    )
+    .unwrap();
+    fs::write(dir.join("web/index.ts"), "import { api } from './api';\n").unwrap();
+    fs::write(dir.join("web/api.ts"), "export const api = 1;\n").unwrap();
+    fs::write(dir.join("app/cli.py"), "from app import models\n").unwrap();
+    fs::write(dir.join("app/models.py"), "class User:\n    pass\n").unwrap();
+
+    let order = |args: &[&str]| -> Vec<String> {
+        let output_path = temp.path().join("output.json");
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(dir.to_str().unwrap())
+            .args(args)
+            .arg("--style")
+            .arg("json")
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .assert()
+            .success();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["path"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let position = |files: &[String], path: &str| files.iter().position(|f| f == path).unwrap();
+
+    // Outside a git repository the default is by path
+    let by_path = order(&[]);
+    let mut sorted = by_path.clone();
+    sorted.sort();
+    assert_eq!(by_path, sorted);
+
+    let by_dependency = order(&["--sort", "dependency"]);
+    let before = |dep: &str, user: &str| {
+        assert!(
+            position(&by_dependency, dep) < position(&by_dependency, user),
+            "{} should precede {}: {:?}",
+            dep,
+            user,
+            by_dependency
+        );
+    };
+    before("src/config.rs", "src/net/client.rs");
+    before("src/net/client.rs", "src/net.rs");
+    before("src/net.rs", "src/main.rs");
+    before("web/api.ts", "web/index.ts");
+    before("app/models.py", "app/cli.py");
+    assert_eq!(by_dependency, order(&["--sort", "dependency"]));
+
+    let by_tokens = order(&["--sort", "tokens"]);
+    assert_eq!(by_tokens.last().unwrap(), "src/net/client.rs");
+
+    // Focused files last, then the files the intent mentions
+    let by_relevance = order(&[
+        "--sort",
+        "relevance",
+        "--compress",
+        "--focus",
+        "web/api.ts",
+        "--intent",
+        "Store the user models",
+    ]);
+    assert_eq!(by_relevance.last().unwrap(), "web/api.ts");
+    assert_eq!(by_relevance[by_relevance.len() - 2], "app/models.py");
+
+    // History orders asked for without history fall back to path, with a warning
+    let warning = |args: &[&str]| -> String {
+        let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg(dir.to_str().unwrap())
+            .args(args)
+            .arg("-o")
+            .arg(temp.path().join("output.xml").to_str().unwrap())
+            .assert()
+            .success();
+        String::from_utf8_lossy(&assert.get_output().stderr).to_string()
+    };
+    assert!(warning(&["--sort", "recency"])
+        .contains("Warning: --sort recency needs a git repository with commits"));
+    assert!(!warning(&[]).contains("needs a git repository"));
+
+    // A repository without commits has no history to sort by yet, which is not an error
+    std::process::Command::new("git")
+        .arg("init")
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert_eq!(order(&["--sort", "churn"]), sorted);
+    assert_eq!(
+        order(&["--sort", "recency", "--file-metadata", "commit"]),
+        sorted
+    );
+    assert!(warning(&["--sort", "churn"])
+        .contains("Warning: --sort churn needs a git repository with commits"));
+}
+
+#[test]
+fn test_remote_subdirectories_commits_and_shorthand() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let git = |dir: &Path, args: &[&str]| -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+    fs::create_dir_all(repo_path.join("pkg/core")).unwrap();
+    fs::write(repo_path.join("pkg/core/lib.rs"), "pub fn v1() {}\n").unwrap();
+    git(&repo_path, &["add", "."]);
+    git(&repo_path, &["commit", "-m", "v1"]);
+    let v1 = git(&repo_path, &["rev-parse", "HEAD"]);
+    git(&repo_path, &["checkout", "-b", "feature/next"]);
+    fs::write(repo_path.join("pkg/core/lib.rs"), "pub fn v2() {}\n").unwrap();
+    fs::write(repo_path.join("pkg/core/extra.rs"), "pub fn extra() {}\n").unwrap();
+    git(&repo_path, &["add", "."]);
+    git(&repo_path, &["commit", "-m", "v2"]);
+    let hosts = temp.path().join("hosts");
+    fs::create_dir_all(hosts.join("acme")).unwrap();
+    git(
+        temp.path(),
+        &[
+            "clone",
+            "--bare",
+            repo_path.to_str().unwrap(),
+            hosts.join("acme/widgets.git").to_str().unwrap(),
+        ],
+    );
+    let url = format!("file://{}", hosts.join("acme/widgets.git").display());
+    // GitHub, redirected here to the local hosts directory
+    let config = temp.path().join("gitconfig");
+    fs::write(
+        &config,
+        format!(
+            "[url \"file://{}/\"]\n\tinsteadOf = https://github.com/\n",
+            hosts.display()
+        ),
+    )
+    .unwrap();
+
+    let packed = |backend: &str, args: &[&str]| -> Vec<(String, String)> {
+        let output_path = temp.path().join("output_remote.json");
+        Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg("--remote")
+            .args(args)
+            .arg("--no-cache")
+            .arg("--git-backend")
+            .arg(backend)
+            .arg("--sort")
+            .arg("path")
+            .arg("--style")
+            .arg("json")
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .current_dir(temp.path())
+            .env("GIT_CONFIG_GLOBAL", &config)
+            .assert()
+            .success();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                (
+                    f["path"].as_str().unwrap().to_string(),
+                    f["content"].as_str().unwrap().trim().to_string(),
+                )
+            })
+            .collect()
+    };
+    let file = |path: &str, content: &str| (path.to_string(), content.to_string());
+
+    // A branch containing `/` is split off a forge's tree URL with --remote-branch
+    let forge = "https://github.com/acme/widgets";
+    assert_eq!(
+        packed(
+            "cli",
+            &[
+                &format!("{}/tree/feature/next/pkg/core", forge),
+                "--remote-branch",
+                "feature/next"
+            ]
+        ),
+        [
+            file("extra.rs", "pub fn extra() {}"),
+            file("lib.rs", "pub fn v2() {}")
+        ]
+    );
+    // A full commit in a tree URL
+    assert_eq!(
+        packed("cli", &[&format!("{}/tree/{}/pkg", forge, v1)]),
+        [file("core/lib.rs", "pub fn v1() {}")]
+    );
+
+    // A local repository inside a directory named `tree` is not a tree URL
+    fs::create_dir_all(temp.path().join("tree")).unwrap();
+    git(
+        temp.path(),
+        &[
+            "clone",
+            "--bare",
+            repo_path.to_str().unwrap(),
+            temp.path().join("tree/widgets.git").to_str().unwrap(),
+        ],
+    );
+    let tree_url = format!("file://{}", temp.path().join("tree/widgets.git").display());
+
+    for backend in ["native", "cli"] {
+        let files = packed(backend, &[&tree_url]);
+        assert!(
+            files.contains(&file("pkg/core/lib.rs", "pub fn v2() {}")),
+            "{}",
+            backend
+        );
+
+        // An abbreviated commit
+        let files = packed(backend, &[&url, "--remote-branch", &v1[..10]]);
+        assert!(files.contains(&file("pkg/core/lib.rs", "pub fn v1() {}")));
+        assert!(files.iter().any(|(p, _)| p == "src/main.rs"));
+
+        // An unknown branch fails without fetching everything first
+        let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg("--remote")
+            .arg(&url)
+            .arg("--remote-branch")
+            .arg("no-such-branch")
+            .arg("--no-cache")
+            .arg("--git-backend")
+            .arg(backend)
+            .current_dir(temp.path())
+            .assert()
+            .failure();
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+        assert!(stderr.contains("no-such-branch"), "{}: {}", backend, stderr);
+    }
+
+    // `owner/repo` expands to GitHub
+    let output_path = temp.path().join("output_shorthand.json");
+    Command::new(env!("CARGO_BIN_EXE_rustymix"))
+        .arg("--remote")
+        .arg("acme/widgets")
+        .arg("--remote-branch")
+        .arg("feature/next")
+        .arg("--git-backend")
+        .arg("cli")
+        .arg("--style")
+        .arg("json")
+        .arg("-o")
+        .arg(output_path.to_str().unwrap())
+        .current_dir(temp.path())
+        .env("RUSTYMIX_CACHE_DIR", temp.path().join("cache"))
+        .env("GIT_CONFIG_GLOBAL", &config)
+        .assert()
+        .success();
+    assert!(fs::read_to_string(&output_path)
+        .unwrap()
+        .contains("pub fn extra() {}"));
+}
+
+#[test]
+fn test_remote_cache_reuses_updates_and_prunes_clones() {
+    let temp = TempDir::new().unwrap();
+    let repo_path = create_rust_repo(temp.path());
+    let git = |dir: &Path, args: &[&str]| -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+    let bare = temp.path().join("remote.git");
+    git(
+        temp.path(),
+        &[
+            "clone",
+            "--bare",
+            repo_path.to_str().unwrap(),
+            bare.to_str().unwrap(),
+        ],
+    );
+    let url = format!("file://{}", bare.display());
+    let cache_dir = temp.path().join("cache");
+    // Entries are directories, each with a lock file beside it
+    fs::create_dir_all(cache_dir.join("remotes")).unwrap();
+    fs::write(cache_dir.join("remotes/0123456789abcdef.lock"), "").unwrap();
+    let entries = || {
+        fs::read_dir(cache_dir.join("remotes"))
+            .map(|dir| dir.filter(|e| e.as_ref().unwrap().path().is_dir()).count())
+            .unwrap_or(0)
+    };
+
+    for backend in ["native", "cli"] {
+        let output_path = temp.path().join(format!("output_cache_{}.xml", backend));
+        let run = |args: &[&str]| -> String {
+            let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+                .arg("--remote")
+                .arg(&url)
+                .args(args)
+                .arg("--git-backend")
+                .arg(backend)
+                .arg("-o")
+                .arg(output_path.to_str().unwrap())
+                .env("RUSTYMIX_CACHE_DIR", &cache_dir)
+                .assert()
+                .success();
+            String::from_utf8(assert.get_output().stdout.clone()).unwrap()
+        };
+
+        assert!(
+            run(&[]).contains("Cloning remote repository"),
+            "{}",
+            backend
+        );
+        assert!(run(&[]).contains("Updating cached clone"), "{}", backend);
+        assert_eq!(entries(), 1);
+
+        // New commits on the remote reach the cached clone
+        fs::write(
+            repo_path.join("CHANGELOG.md"),
+            format!("{} update\n", backend),
+        )
+        .unwrap();
+        git(&repo_path, &["add", "."]);
+        git(&repo_path, &["commit", "-m", "Update"]);
+        let head = git(&repo_path, &["rev-parse", "HEAD"]);
+        git(
+            &repo_path,
+            &["push", "--quiet", bare.to_str().unwrap(), "HEAD"],
+        );
+        run(&[]);
+        let pack = fs::read_to_string(&output_path).unwrap();
+        assert!(pack.contains(&format!("{} update", backend)), "{}", backend);
+
+        // A pinned commit is its own entry and is not fetched again
+        assert!(run(&["--remote-branch", &head]).contains("Cloning remote repository"));
+        assert!(run(&["--remote-branch", &head]).contains("Using cached clone"));
+        assert_eq!(entries(), 2);
+
+        // --no-cache leaves the cache alone
+        assert!(run(&["--no-cache"]).contains("Cloning remote repository"));
+        assert_eq!(entries(), 2);
+
+        // Without the remote, a clone holding its own objects is used as it is; the
+        // built-in backend's clone borrowed them from the remote and cannot be
+        let moved = temp.path().join("moved.git");
+        fs::rename(&bare, &moved).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .arg("--remote")
+            .arg(&url)
+            .arg("--git-backend")
+            .arg(backend)
+            .arg("-o")
+            .arg(output_path.to_str().unwrap())
+            .env("RUSTYMIX_CACHE_DIR", &cache_dir)
+            .output()
+            .unwrap();
+        fs::rename(&moved, &bare).unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if backend == "cli" {
+            assert!(output.status.success(), "{}", stderr);
+            assert!(stderr.contains("Warning: cannot update the cached clone"));
+            let pack = fs::read_to_string(&output_path).unwrap();
+            assert!(pack.contains(&format!("{} update", backend)));
+        } else {
+            assert!(!output.status.success());
+        }
+        assert_eq!(entries(), 2);
+
+        // Pack flags do not apply to `cache`, before or after its action
+        for args in [
+            &["cache", "prune", "--all", "--style", "json"][..],
+            &["--focus", "src", "cache", "prune", "--all"][..],
+        ] {
+            let assert = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+                .args(args)
+                .env("RUSTYMIX_CACHE_DIR", &cache_dir)
+                .assert()
+                .failure();
+            let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+            assert!(stderr.contains("unexpected argument"), "{}", stderr);
+        }
+        assert_eq!(entries(), 2);
+
+        let prune = Command::new(env!("CARGO_BIN_EXE_rustymix"))
+            .args(["cache", "prune", "--all"])
+            .env("RUSTYMIX_CACHE_DIR", &cache_dir)
+            .assert()
+            .success();
+        let stdout = String::from_utf8(prune.get_output().stdout.clone()).unwrap();
+        assert!(stdout.contains("Removed 2 cached clone(s)"), "{}", stdout);
+        assert_eq!(entries(), 0);
+        // Lock files go with their entries, and stray ones are swept up
+        assert_eq!(
+            fs::read_dir(cache_dir.join("remotes")).unwrap().count(),
+            0,
+            "{}",
+            backend
+        );
+    }
+}